@@ -1,10 +1,36 @@
+use crate::command::Timeouts;
+use crate::cuts;
+use crate::cuts::CutSource;
+use crate::disk_budget;
+use crate::encoder::Codec;
+use crate::encoder::LosslessCodec;
+use crate::encoder::Preset;
+use crate::interpolator::Backend;
+use crate::interpolator::RetryPolicy;
+use crate::progress::ProgressMode;
+use crate::scenes::AdaptiveThreshold;
+use crate::scenes::FadeDetection;
+use crate::scenes::SceneDetection;
+use crate::window::Outside;
+use crate::window::Window;
 use crate::ResetData;
+use rust_decimal::Decimal;
+use std::num::NonZeroU64;
 use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use std::time::Duration;
 
 use crate::NonZeroDecimal;
+use clap::Args;
 use clap::Parser;
+use clap::Subcommand;
 
+use crate::Encoder;
+use crate::Error;
+use crate::FfmpegInterpolator;
+use crate::FrameGenerator;
+use crate::Interpolator;
+use crate::Target;
 use crate::FPS;
 
 #[derive(Debug, Parser)]
@@ -19,18 +45,53 @@ pub struct Cli {
     pub output: PathBuf,
     ///
     /// AI Model used to generate intermediate frames
+    /// Not needed for the minterpolate/blend backends
     #[arg(value_parser=is_file, env)]
-    pub ai_binary: PathBuf,
+    pub ai_binary: Option<PathBuf>,
 
     #[arg(value_parser=is_dir, env)]
-    pub ai_model: PathBuf,
+    pub ai_model: Option<PathBuf>,
+
+    /// Which kind of ai binary AI_BINARY is, so we know which arguments to pass
+    /// minterpolate and blend use ffmpeg instead, so they work without an ai binary or gpu
+    #[arg(long, default_value_t = Backend::default())]
+    pub backend: Backend,
+
+    /// Arguments for the custom backend
+    /// {model}, {input}, {output} and {count} are replaced with the model path, the frames
+    /// directory, the generated frames directory and the number of frames to generate
+    #[arg(long, required_if_eq("backend", "custom"))]
+    pub ai_template: Option<String>,
+
+    /// Arguments for the custom backend when using --stream, for binaries that read raw frames
+    /// from stdin and write them to stdout
+    /// {model}, {width}, {height}, {pix_fmt}, {input_count} and {count} are replaced
+    #[arg(long)]
+    pub ai_stream_template: Option<String>,
+
+    /// For the custom backend, if it can't target a frame count (no {count} in the template)
+    /// and always multiplies the frame count by this much
+    #[arg(long, requires = "ai_template")]
+    pub ai_fixed_multiplier: Option<NonZeroU64>,
+
+    /// Glob matching the frames the ai binary creates
+    #[arg(long, default_value_t = String::from("*.png"))]
+    pub ai_output_glob: String,
 
     /// The target frame count for the ai binary
     /// The default will have the ai binary change your (most likely 24fps) video to
     /// 60fps
-    #[arg(long, value_enum, default_value_t=FPS::default())]
+    /// Accepts whole numbers (120, 144), ratios (60000/1001) or decimals (59.94, which
+    /// becomes 60000/1001)
+    #[arg(long, default_value_t=FPS::default())]
     pub fps: FPS,
 
+    /// Multiply the source's frame rate instead of targeting a fixed --fps
+    /// e.g. 2 turns 24fps into 48fps, keeping the original frames and generating 1 frame
+    /// between each pair
+    #[arg(long, conflicts_with = "fps")]
+    pub multiplier: Option<NonZeroU64>,
+
     /// Path to put temporary/intermediate data
     /// like ffmpeg generated frames and ai generated frames
     /// If the path doesn't exist, it will be created
@@ -38,14 +99,130 @@ pub struct Cli {
     #[arg(short, value_parser=dne_or_is_dir)]
     pub temp_dir: PathBuf,
 
-    /// Maximum number of seconds to extract (assuming the scene splits are too big)
-    #[arg(short='m', default_value_t = NonZeroUsize::new(50).unwrap())]
-    pub max_step_size: NonZeroUsize,
+    /// Longest a chunk can be in seconds, longer scenes are split into several chunks
+    #[arg(short='m', long, default_value_t = Decimal::from(50), value_parser=positive)]
+    pub max_scene_length: Decimal,
+
+    /// Scene changes closer together than this many seconds are merged into the strongest one,
+    /// so flashes/flickering lights don't create lots of tiny chunks
+    #[arg(long, default_value_t = Decimal::ZERO, value_parser=non_negative)]
+    pub min_scene_length: Decimal,
+
+    /// After a scene change, the scene score has to drop below -s minus this before there can
+    /// be another one
+    /// Not set means every frame above -s is a scene change
+    #[arg(long, value_parser=non_negative)]
+    pub scene_hysteresis: Option<Decimal>,
+
+    /// Keep the extracted + generated frames in the temp dir under this many bytes, by making
+    /// the chunks smaller than -m if needed
+    /// Accepts K, M, G and T suffixes, e.g. 20G
+    #[arg(long, value_parser=disk_budget::parse_bytes)]
+    pub max_temp_bytes: Option<NonZeroU64>,
 
     /// Extra args you may want to pass to the ai binary
     #[arg(long, default_value_t = default_ai_args())]
     pub ai_args: String,
 
+    /// How many times to try a chunk again when the ai binary fails on it
+    #[arg(long, default_value_t = 2)]
+    pub ai_retries: usize,
+
+    /// Seconds to wait before trying a chunk again, doubled after every retry
+    #[arg(long, default_value_t = 5)]
+    pub ai_retry_backoff: u64,
+
+    /// Seconds the ai binary can take on a chunk before it's killed (and retried)
+    /// Runs forever if not set
+    #[arg(long)]
+    pub ai_timeout: Option<NonZeroU64>,
+
+    /// Seconds each ffmpeg command (extracting/encoding a chunk, putting the chunks together)
+    /// can take before it's killed
+    /// Runs forever if not set
+    #[arg(long)]
+    pub ffmpeg_timeout: Option<NonZeroU64>,
+
+    /// Seconds each ffprobe command can take before it's killed
+    /// Keep in mind scene detection reads the whole input in one go
+    /// Runs forever if not set
+    #[arg(long)]
+    pub ffprobe_timeout: Option<NonZeroU64>,
+
+    /// --ai-args to try once more when the retries run out, e.g. a smaller tile size or -g -1
+    /// for the cpu
+    /// If that fails too, the other chunks carry on and the failed ones are listed at the end
+    #[arg(long, allow_hyphen_values = true)]
+    pub ai_fallback_args: Option<String>,
+
+    /// Interpolate several chunks at once, one per --worker (e.g. one per GPU)
+    /// Each worker passes these args to the ai binary instead of --ai-args
+    /// e.g. --worker '-g 0 -j 2:2:2' --worker '-g 1 -j 2:2:2'
+    #[arg(long = "worker", allow_hyphen_values = true)]
+    pub workers: Vec<String>,
+
+    /// How to show progress: a bar, a line per chunk, or a json object per chunk on stdout
+    #[arg(long, default_value_t = ProgressMode::default())]
+    pub progress: ProgressMode,
+
+    /// Only count a frame as a scene change if its score is far above the scores around it, so
+    /// fast action isn't split up too much
+    /// -s is still the lowest score a scene change can have, so it can be lowered (e.g. to .05)
+    /// to find the scene changes in dark scenes too
+    #[arg(long)]
+    pub adaptive_scenes: bool,
+
+    /// Seconds of frames on each side of a frame that --adaptive-scenes compares it to
+    #[arg(long, default_value_t = Decimal::ONE, value_parser=positive)]
+    pub adaptive_window: Decimal,
+
+    /// How far above the frames around it (in median absolute deviations) a frame's score has
+    /// to be for --adaptive-scenes
+    #[arg(long, default_value_t = Decimal::from(5), value_parser=positive)]
+    pub adaptive_sensitivity: Decimal,
+
+    /// Find fades/dissolves: frames that each score above this (but below -s) and add up to more
+    /// than -s. They're passed through with their frames duplicated instead of interpolated,
+    /// because interpolating them ghosts badly
+    /// Try .02
+    #[arg(long, value_parser=non_negative)]
+    pub fade_threshold: Option<Decimal>,
+
+    /// Shortest a fade can be in seconds
+    #[arg(long, default_value_t = Decimal::new(25, 2), value_parser=non_negative)]
+    pub min_fade_length: Decimal,
+
+    /// Longest a fade can be in seconds, longer runs are usually the camera moving
+    #[arg(long, default_value_t = Decimal::from(3), value_parser=non_negative)]
+    pub max_fade_length: Decimal,
+
+    /// Where the scene changes come from, e.g. --cuts chapters,detected uses both
+    #[arg(long, value_delimiter = ',', default_value = "detected")]
+    pub cuts: Vec<CutSource>,
+
+    /// Scene changes from an edit, one timestamp (83.5 or 00:01:23.5) per line, the first
+    /// column of a .csv or the record in timecodes of a CMX 3600 .edl
+    /// Used along with --cuts (so with the detected ones by default)
+    #[arg(long, value_parser=is_file)]
+    pub cut_file: Option<PathBuf>,
+
+    /// Only process the input from this many seconds (or hh:mm:ss.sss) in, e.g. to preview a
+    /// few seconds before doing the whole thing
+    #[arg(long, value_parser=timestamp, conflicts_with = "segments")]
+    pub start: Option<Decimal>,
+
+    /// Only process the input up to this many seconds (or hh:mm:ss.sss) in
+    #[arg(long, value_parser=timestamp, conflicts_with = "segments")]
+    pub end: Option<Decimal>,
+
+    /// Only process these parts of the input, e.g. --segments 1:00-1:30,1:02:10-1:02:20.5
+    #[arg(long, value_delimiter = ',')]
+    pub segments: Vec<Window>,
+
+    /// What to do with the rest of the input with --start, --end or --segments
+    #[arg(long, default_value_t = Outside::default())]
+    pub outside: Outside,
+
     /// Clears cached data
     #[arg(short='r', default_value_t = ResetData::default())]
     pub reset: ResetData,
@@ -53,12 +230,218 @@ pub struct Cli {
     /// Defines how we should split the video up before generating frames
     /// If there is a big difference between frames, the ai will generate
     /// bad frames.
+    /// The score of every frame is cached, so this can be changed with -r nothing without
+    /// reading the whole video again
     #[arg(short='s', default_value_t = String::from(".1"), value_parser=can_be_decimal)]
     pub scene_gt: String,
 
     /// https://trac.ffmpeg.org/wiki/Encode/H.264#a1.ChooseaCRFvalue
     #[arg(long, default_value_t = NonZeroUsize::new(18).unwrap())]
     pub crf: NonZeroUsize,
+
+    /// Video encoder used for the generated frames
+    /// Chunks are stored as mkv if the input's container can't hold the codec, and the output's
+    /// container has to be able to hold it
+    #[arg(long, default_value_t = Codec::default())]
+    pub codec: Codec,
+
+    /// Encoder speed vs compression tradeoff (translated for libsvtav1 and libvpx-vp9)
+    /// https://trac.ffmpeg.org/wiki/Encode/H.264#Preset
+    #[arg(long)]
+    pub preset: Option<Preset>,
+
+    /// Pixel format of the encoded video, e.g. yuv420p10le for 10-bit video
+    #[arg(long, default_value_t = String::from("yuv420p"))]
+    pub pix_fmt: String,
+
+    /// Encode each chunk losslessly, then encode the whole video once with the --codec,
+    /// --preset, --crf, etc. settings
+    /// Avoids quality changes/keyframes at every chunk boundary, but the chunks take up a lot
+    /// more space and the final encode takes longer
+    #[arg(long)]
+    pub lossless_chunks: Option<LosslessCodec>,
+
+    /// Pipe raw frames from ffmpeg to the interpolator to the encoder, instead of writing png
+    /// files to the temp dir
+    /// Only for backends that support stdin/stdout (minterpolate, blend, or custom with
+    /// --ai-stream-template), the others fall back to png files
+    #[arg(long)]
+    pub stream: bool,
+
+    /// Extra args you may want to pass to ffmpeg when encoding
+    #[arg(long, default_value_t = String::new(), hide_default_value = true, allow_hyphen_values = true)]
+    pub encoder_args: String,
+
+    /// Don't delete the temp dir when we're done, so chunks can be created again with rerender
+    #[arg(long)]
+    pub keep_temp_dir: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Create some chunks' videos again (e.g. with different --ai-args, -s or --backend), then
+    /// write the output again with every other chunk as it was
+    /// Needs the temp dir of a previous run, see --keep-temp-dir
+    Rerender(Rerender),
+}
+
+/// Which chunks to create again
+#[derive(Debug, Args)]
+#[group(required = true, multiple = true)]
+pub struct Rerender {
+    /// Chunk numbers, as in the temp dir's manifest.json (starting at 0)
+    #[arg(long, value_delimiter = ',')]
+    pub chunks: Vec<usize>,
+
+    /// The chunks with these times (seconds or hh:mm:ss.sss) of the input in them
+    #[arg(long, value_delimiter = ',', value_parser=timestamp)]
+    pub at: Vec<Decimal>,
+}
+
+impl Cli {
+    /// `--multiplier` wins over the (defaulted) `--fps`
+    pub fn target(&self) -> Target {
+        match self.multiplier {
+            Some(multiplier) => Target::Multiplier(multiplier),
+            None => Target::Fps(self.fps),
+        }
+    }
+
+    /// The encoder for the final video
+    pub fn encoder(&self) -> Encoder {
+        Encoder {
+            codec: self.codec,
+            preset: self.preset,
+            crf: self.crf,
+            lossless: false,
+            pix_fmt: self.pix_fmt.clone(),
+            extra_args: self.encoder_args.clone(),
+        }
+    }
+
+    /// The encoder for each chunk, which is the final encoder unless --lossless-chunks is used
+    pub fn chunk_encoder(&self) -> Encoder {
+        match self.lossless_chunks {
+            Some(lossless_codec) => lossless_codec.encoder(&self.pix_fmt),
+            None => self.encoder(),
+        }
+    }
+
+    /// One `Interpolator` per --worker (or just one using --ai-args)
+    pub fn interpolators(&self) -> Result<Vec<Box<dyn Interpolator + '_>>, Error> {
+        if self.workers.is_empty() {
+            return Ok(vec![self.interpolator(&self.ai_args)?]);
+        }
+        self.workers
+            .iter()
+            .map(|worker_args| self.interpolator(worker_args))
+            .collect()
+    }
+
+    /// The `Interpolator` for --ai-fallback-args
+    pub fn fallback_interpolator(&self) -> Result<Option<Box<dyn Interpolator + '_>>, Error> {
+        self.ai_fallback_args
+            .as_deref()
+            .map(|ai_args| self.interpolator(ai_args))
+            .transpose()
+    }
+
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            retries: self.ai_retries,
+            backoff: Duration::from_secs(self.ai_retry_backoff),
+        }
+    }
+
+    /// Converts the scene lengths from seconds to frames of the input
+    pub fn scene_detection(&self, source_fps: FPS) -> Result<SceneDetection, Error> {
+        let max_frames = source_fps.frame_at(&self.max_scene_length)?;
+        Ok(SceneDetection {
+            threshold: Decimal::from_str_exact(&self.scene_gt)?,
+            min_frames: source_fps.frame_at(&self.min_scene_length)?,
+            max_frames: NonZeroUsize::new(max_frames).unwrap_or(NonZeroUsize::MIN),
+            hysteresis: self.scene_hysteresis,
+            adaptive: self
+                .adaptive_scenes
+                .then(|| {
+                    Ok::<_, Error>(AdaptiveThreshold {
+                        window: source_fps.frame_at(&self.adaptive_window)?,
+                        sensitivity: self.adaptive_sensitivity,
+                    })
+                })
+                .transpose()?,
+            fades: self
+                .fade_threshold
+                .map(|threshold| {
+                    Ok::<_, Error>(FadeDetection {
+                        threshold,
+                        min_frames: source_fps.frame_at(&self.min_fade_length)?,
+                        max_frames: source_fps.frame_at(&self.max_fade_length)?,
+                    })
+                })
+                .transpose()?,
+        })
+    }
+
+    /// The parts of the input to process, none means all of it
+    pub fn windows(&self) -> Vec<Window> {
+        match (self.start, self.end) {
+            (None, None) => self.segments.clone(),
+            (start, end) => vec![Window {
+                start: start.unwrap_or_default(),
+                end,
+            }],
+        }
+    }
+
+    /// The --cuts, plus the --cut-file if there is one
+    pub fn cut_sources(&self) -> Result<Vec<CutSource>, Error> {
+        let mut sources = self.cuts.clone();
+        match (sources.contains(&CutSource::File), &self.cut_file) {
+            (true, None) => return Err(Error::MissingCutFile),
+            (false, Some(_)) => sources.push(CutSource::File),
+            _ => {}
+        }
+        sources.sort_unstable_by_key(|source| *source as u8);
+        sources.dedup();
+        Ok(sources)
+    }
+
+    pub fn timeouts(&self) -> Timeouts {
+        let seconds = |seconds: Option<NonZeroU64>| seconds.map(|s| Duration::from_secs(s.get()));
+        Timeouts {
+            ffprobe: seconds(self.ffprobe_timeout),
+            ffmpeg: seconds(self.ffmpeg_timeout),
+            ai: seconds(self.ai_timeout),
+        }
+    }
+
+    /// The `Interpolator` for the `--backend` option
+    fn interpolator<'a>(&'a self, ai_args: &'a str) -> Result<Box<dyn Interpolator + 'a>, Error> {
+        let interpolator: Box<dyn Interpolator> = match self.backend {
+            Backend::Minterpolate => Box::new(FfmpegInterpolator::Minterpolate),
+            Backend::Blend => Box::new(FfmpegInterpolator::Blend),
+            backend => {
+                let (Some(binary), Some(model)) = (&self.ai_binary, &self.ai_model) else {
+                    return Err(Error::MissingAiBinary(backend));
+                };
+                Box::new(FrameGenerator {
+                    binary,
+                    model,
+                    backend,
+                    template: self.ai_template.as_deref(),
+                    stream_template: self.ai_stream_template.as_deref(),
+                    fixed_multiplier: self.ai_fixed_multiplier,
+                    output_glob: &self.ai_output_glob,
+                    extra_args: ai_args,
+                })
+            }
+        };
+        Ok(interpolator)
+    }
 }
 
 fn can_be_decimal(scene_gt: &str) -> Result<String, String> {
@@ -67,6 +450,25 @@ fn can_be_decimal(scene_gt: &str) -> Result<String, String> {
     Ok(scene_gt.to_owned())
 }
 
+fn non_negative(decimal: &str) -> Result<Decimal, String> {
+    Decimal::from_str_exact(decimal)
+        .ok()
+        .filter(|decimal| !decimal.is_sign_negative())
+        .ok_or_else(|| format!("should be a number that isn't negative: {decimal}"))
+}
+
+fn positive(decimal: &str) -> Result<Decimal, String> {
+    match non_negative(decimal)? {
+        parsed if parsed.is_zero() => Err(format!("should be more than 0: {decimal}")),
+        parsed => Ok(parsed),
+    }
+}
+
+fn timestamp(timestamp: &str) -> Result<Decimal, String> {
+    cuts::parse_timestamp(timestamp)
+        .ok_or_else(|| format!("should be seconds or hh:mm:ss.sss: {timestamp}"))
+}
+
 /// Confirm the path exists + is a file
 fn is_file(path: &str) -> Result<PathBuf, String> {
     let path = PathBuf::from(path);
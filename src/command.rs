@@ -1,8 +1,242 @@
 use crate::Error;
 use log::debug;
+use std::collections::VecDeque;
 use std::env;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
 use std::path::PathBuf;
+use std::process;
+use std::process::Child;
 use std::process::Command;
+use std::process::ExitStatus;
+use std::process::Stdio;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::OnceLock;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How many lines of stderr are kept in a `CommandFailure`, the whole thing goes to the log file
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Where failed commands are logged, see `set_log_file`
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// How long each kind of command gets, see `set_timeouts`
+static TIMEOUTS: OnceLock<Timeouts> = OnceLock::new();
+
+/// Set by `interrupt`, running commands get killed + new ones aren't started
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// The longest we wait between checking if a command exited, timed out or was interrupted
+const MAX_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The binaries we run, so errors + timeouts can be told apart
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Program {
+    Ffprobe,
+    Ffmpeg,
+    AI,
+}
+
+impl Program {
+    fn error(self, failure: CommandFailure) -> Error {
+        match self {
+            Self::Ffprobe => Error::FfprobeCommand(failure),
+            Self::Ffmpeg => Error::FfmpegCommand(failure),
+            Self::AI => Error::AICommand(failure),
+        }
+    }
+
+    fn timeout(self) -> Option<Duration> {
+        let timeouts = TIMEOUTS.get()?;
+        match self {
+            Self::Ffprobe => timeouts.ffprobe,
+            Self::Ffmpeg => timeouts.ffmpeg,
+            Self::AI => timeouts.ai,
+        }
+    }
+}
+
+/// How long a command of each `Program` can run before it's killed, `None` means forever
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct Timeouts {
+    pub ffprobe: Option<Duration>,
+    pub ffmpeg: Option<Duration>,
+    pub ai: Option<Duration>,
+}
+
+/// Only the first call does anything
+pub fn set_timeouts(timeouts: Timeouts) {
+    TIMEOUTS.get_or_init(|| timeouts);
+}
+
+/// Kills the running commands (and anything they started), and makes every command after this
+/// fail with `Error::Interrupted`
+/// Safe to call from a signal handler's thread
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Every failed command's full stderr gets appended to `log_file` (see `ReusableTempDir`)
+/// Only the first call does anything
+pub fn set_log_file(log_file: PathBuf) {
+    LOG_FILE.get_or_init(|| log_file);
+}
+
+/// The file set by `set_log_file`
+pub fn log_file() -> Option<&'static PathBuf> {
+    LOG_FILE.get()
+}
+
+/// What went wrong with a command, so failures can be debugged without running it again
+#[derive(Debug)]
+pub struct CommandFailure {
+    /// the binary + args, as they would be typed into a shell
+    pub command: String,
+    pub status: String,
+    /// the last `STDERR_TAIL_LINES` lines
+    pub stderr_tail: String,
+}
+
+impl CommandFailure {
+    fn new(binary: &str, args: &str, status: String, stderr: Stderr) -> Self {
+        let command = format!("{binary} {args}");
+        let stderr_tail = stderr.tail();
+        log_failure(&command, &status, stderr);
+        Self {
+            command,
+            status,
+            stderr_tail,
+        }
+    }
+
+    /// e.g. the binary isn't installed
+    fn not_started(binary: &str, args: &str, error: io::Error) -> Self {
+        let command = format!("{binary} {args}");
+        let status = format!("unable to start: {error}");
+        log_failure(&command, &status, Stderr::default());
+        Self {
+            command,
+            status,
+            stderr_tail: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for CommandFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` failed ({})", self.command, self.status)?;
+        if !self.stderr_tail.is_empty() {
+            write!(f, "\n{}", self.stderr_tail)?;
+        }
+        Ok(())
+    }
+}
+
+/// Logging is best effort, the error is still returned if this fails
+fn log_failure(command: &str, status: &str, stderr: Stderr) {
+    let Some(log_file) = LOG_FILE.get() else {
+        return;
+    };
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    let header = format!("[{timestamp}] `{command}` failed ({status})\n");
+    let _ = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_file)
+        .and_then(|mut file| {
+            file.write_all(header.as_bytes())?;
+            stderr.copy_to(&mut file)?;
+            file.write_all(b"\n\n")
+        });
+}
+
+/// A command's stderr, without keeping all of it in memory (ai binaries can print a line for
+/// every frame)
+/// The last `STDERR_TAIL_LINES` lines are kept for the error, and every line is written to a
+/// spool file next to the log file (see `set_log_file`) in case the command fails
+#[derive(Debug, Default)]
+struct Stderr {
+    tail: VecDeque<String>,
+    /// deleted once we're done with it
+    spool: Option<(PathBuf, BufWriter<File>)>,
+}
+
+impl Stderr {
+    /// Without a log file, only the tail is kept
+    fn new() -> Self {
+        static SPOOLS: AtomicUsize = AtomicUsize::new(0);
+        let spool = LOG_FILE.get().and_then(|log_file| {
+            let spool = SPOOLS.fetch_add(1, Ordering::Relaxed);
+            let path = log_file.with_file_name(format!("stderr-{}-{spool}.log", process::id()));
+            let file = File::create(&path).ok()?;
+            Some((path, BufWriter::new(file)))
+        });
+        Self {
+            tail: VecDeque::with_capacity(STDERR_TAIL_LINES),
+            spool,
+        }
+    }
+
+    fn push(&mut self, line: &str) {
+        if let Some((_, spool)) = &mut self.spool {
+            // only for the log, so not worth failing the command over
+            let _ = writeln!(spool, "{line}");
+        }
+        if self.tail.len() == STDERR_TAIL_LINES {
+            self.tail.pop_front();
+        }
+        self.tail.push_back(line.to_owned());
+    }
+
+    /// The last `STDERR_TAIL_LINES` lines
+    fn tail(&self) -> String {
+        let lines = self.tail.iter().map(String::as_str).collect::<Vec<_>>();
+        lines.join("\n").trim_end().to_owned()
+    }
+
+    /// Copies all of it to `writer`, or just the tail if there's no spool file
+    fn copy_to(mut self, writer: &mut impl Write) -> io::Result<()> {
+        match self.spool.take() {
+            Some((path, spool)) => {
+                spool.into_inner().map_err(|e| e.into_error())?;
+                let copied = File::open(&path).and_then(|mut spool| io::copy(&mut spool, writer));
+                fs::remove_file(path)?;
+                copied.map(|_| ())
+            }
+            None => writer.write_all(self.tail().as_bytes()),
+        }
+    }
+}
+
+impl Drop for Stderr {
+    fn drop(&mut self) {
+        if let Some((path, _)) = self.spool.take() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct Requirements<'a> {
@@ -18,23 +252,462 @@ impl<'a> TryFrom<&'a str> for Requirements<'a> {
     }
 }
 
-pub fn run(binary: &str, requirements: Requirements, error: Error) -> Result<String, Error> {
+/// Runs `binary`, returning its stdout
+/// The command is killed if it takes longer than `program`'s timeout (see `set_timeouts`)
+pub fn run(binary: &str, requirements: Requirements, program: Program) -> Result<String, Error> {
+    let mut stdout = String::new();
+    let on_stdout = |line: &str| {
+        stdout.push_str(line);
+        stdout.push('\n');
+    };
+    run_with_timeout(
+        binary,
+        requirements,
+        program,
+        program.timeout(),
+        on_stdout,
+        |_| {},
+    )?;
+    Ok(stdout)
+}
+
+/// Like `run`, but `on_stdout` + `on_stderr` are called with each line as soon as it's written,
+/// instead of keeping all of stdout in memory
+/// Handy for commands with a lot of output, or to show how far along they are
+pub fn run_lines(
+    binary: &str,
+    requirements: Requirements,
+    program: Program,
+    on_stdout: impl FnMut(&str) + Send,
+    on_stderr: impl FnMut(&str) + Send,
+) -> Result<(), Error> {
+    run_with_timeout(
+        binary,
+        requirements,
+        program,
+        program.timeout(),
+        on_stdout,
+        on_stderr,
+    )
+}
+
+fn run_with_timeout(
+    binary: &str,
+    requirements: Requirements,
+    program: Program,
+    timeout: Option<Duration>,
+    on_stdout: impl FnMut(&str) + Send,
+    mut on_stderr: impl FnMut(&str) + Send,
+) -> Result<(), Error> {
     debug!(
         "cd {} && {binary} {};",
         requirements.current_dir.display(),
         requirements.args
     );
     let args = shell_words::split(requirements.args)?;
-    let output = Command::new(binary)
+    let mut command = Command::new(binary);
+    command
         .args(args)
         .current_dir(requirements.current_dir)
-        .output()?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    if !output.status.success() {
-        debug!("stdout: {stdout}");
-        debug!("stderr: {}", String::from_utf8_lossy(&output.stderr));
-        return Err(error);
-    }
-    debug!("Finished executing command");
-    Ok(stdout.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = spawn(&mut command, binary, requirements.args, program)?;
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+
+    // both pipes are read while waiting, otherwise a full pipe would block the child
+    let (waited, stderr) = thread::scope(|scope| {
+        scope.spawn(move || read_lines(stdout, on_stdout));
+        // stderr is kept for the error
+        let stderr = scope.spawn(move || {
+            let mut output = Stderr::new();
+            read_lines(stderr, |line| {
+                on_stderr(line);
+                output.push(line);
+            });
+            output
+        });
+        let waited = wait(std::slice::from_mut(&mut child), &[timeout]);
+        (waited, stderr.join().unwrap_or_default())
+    });
+    let status = match waited? {
+        Waited::Exited(statuses) if statuses[0].success() => {
+            debug!("Finished executing command");
+            return Ok(());
+        }
+        Waited::Exited(statuses) => statuses[0].to_string(),
+        Waited::TimedOut(_, timeout) => timed_out(timeout),
+        Waited::Interrupted => return Err(Error::Interrupted),
+    };
+    debug!("stderr: {}", stderr.tail());
+    Err(program.error(CommandFailure::new(
+        binary,
+        requirements.args,
+        status,
+        stderr,
+    )))
+}
+
+/// One step of a pipeline, see `run_piped`
+#[derive(Debug)]
+pub struct PipedCommand {
+    pub binary: String,
+    pub args: String,
+    /// decides the error + timeout, see `run`
+    pub program: Program,
+}
+
+/// Runs the commands at the same time, with each command's stdout going to the next command's
+/// stdin (like `a | b | c` in a shell)
+/// If a command fails, the commands before it usually fail too because of the broken pipe, so
+/// the error of the last failing command is returned
+/// If any command runs for longer than its `program`'s timeout, they're all killed (and that
+/// command gets the blame)
+pub fn run_piped(commands: Vec<PipedCommand>) -> Result<(), Error> {
+    let timeouts = commands
+        .iter()
+        .map(|command| command.program.timeout())
+        .collect::<Vec<_>>();
+    run_piped_with_timeouts(commands, &timeouts)
+}
+
+fn run_piped_with_timeouts(
+    commands: Vec<PipedCommand>,
+    timeouts: &[Option<Duration>],
+) -> Result<(), Error> {
+    debug!(
+        "{};",
+        commands
+            .iter()
+            .map(|command| format!("{} {}", command.binary, command.args))
+            .collect::<Vec<_>>()
+            .join(" | ")
+    );
+    let last = commands.len().saturating_sub(1);
+    let mut previous_stdout = None;
+    let mut children = vec![];
+    let mut stderrs = vec![];
+    for (index, command) in commands.iter().enumerate() {
+        let args = shell_words::split(&command.args)?;
+        let stdin = previous_stdout.take().map_or_else(Stdio::null, Stdio::from);
+        let stdout = if index == last {
+            Stdio::null()
+        } else {
+            Stdio::piped()
+        };
+        let mut process = Command::new(&command.binary);
+        process
+            .args(args)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(Stdio::piped());
+        let mut child = match spawn(
+            &mut process,
+            &command.binary,
+            &command.args,
+            command.program,
+        ) {
+            Ok(child) => child,
+            Err(e) => {
+                // the ones already started would otherwise be left running
+                for child in &mut children {
+                    kill(child);
+                    child.wait()?;
+                }
+                return Err(e);
+            }
+        };
+        previous_stdout = child.stdout.take();
+        stderrs.push(read_stderr(child.stderr.take()));
+        children.push(child);
+    }
+
+    let waited = wait(&mut children, timeouts)?;
+    let stderrs = stderrs
+        .into_iter()
+        .map(|stderr| stderr.join().unwrap_or_default())
+        .collect::<Vec<_>>();
+
+    // `None` for the commands that didn't fail
+    let statuses: Vec<Option<String>> = match waited {
+        Waited::Exited(statuses) => statuses
+            .iter()
+            .map(|status| (!status.success()).then(|| status.to_string()))
+            .collect(),
+        Waited::TimedOut(blamed, timeout) => (0..commands.len())
+            .map(|index| (index == blamed).then(|| timed_out(timeout)))
+            .collect(),
+        Waited::Interrupted => return Err(Error::Interrupted),
+    };
+    let mut failure = None;
+    for ((command, status), stderr) in commands.iter().zip(statuses).zip(stderrs) {
+        if let Some(status) = status {
+            debug!("{} stderr: {}", command.binary, stderr.tail());
+            let details = CommandFailure::new(&command.binary, &command.args, status, stderr);
+            failure = Some(command.program.error(details));
+        }
+    }
+    match failure {
+        Some(error) => Err(error),
+        None => {
+            debug!("Finished executing commands");
+            Ok(())
+        }
+    }
+}
+
+/// Starts `command` in its own process group, so it (and anything it starts) can be killed
+/// without taking us down with it
+fn spawn(
+    command: &mut Command,
+    binary: &str,
+    args: &str,
+    program: Program,
+) -> Result<Child, Error> {
+    if interrupted() {
+        return Err(Error::Interrupted);
+    }
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(command, 0);
+    command
+        .spawn()
+        .map_err(|e| program.error(CommandFailure::not_started(binary, args, e)))
+}
+
+/// Reads on another thread, otherwise a full pipe would block the child
+fn read_stderr(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Stderr> {
+    thread::spawn(move || {
+        let mut stderr = Stderr::new();
+        read_lines(pipe, |line| stderr.push(line));
+        stderr
+    })
+}
+
+/// Calls `on_line` with each line (without the line ending) until the pipe is closed
+/// Invalid utf-8 is replaced rather than stopping early
+fn read_lines(pipe: Option<impl Read>, mut on_line: impl FnMut(&str)) {
+    let Some(pipe) = pipe else {
+        return;
+    };
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&line);
+                on_line(line.trim_end_matches(['\r', '\n']));
+            }
+        }
+    }
+}
+
+/// How `wait` ended
+enum Waited {
+    /// in the same order as the children
+    Exited(Vec<ExitStatus>),
+    /// the index of the child that ran out of time, and its timeout
+    TimedOut(usize, Duration),
+    Interrupted,
+}
+
+/// Waits for all the `children` to exit, killing all of them once any of them runs for longer
+/// than its timeout (in the same order, `None` is forever) or we're interrupted
+fn wait(children: &mut [Child], timeouts: &[Option<Duration>]) -> Result<Waited, Error> {
+    let started = Instant::now();
+    let mut statuses = vec![None; children.len()];
+    // short commands (like most ffprobe calls) shouldn't have to wait for a whole interval
+    let mut poll_interval = Duration::from_millis(1);
+    loop {
+        for (child, status) in children.iter_mut().zip(&mut statuses) {
+            if status.is_none() {
+                *status = child.try_wait()?;
+            }
+        }
+        if statuses.iter().all(Option::is_some) {
+            return Ok(Waited::Exited(statuses.into_iter().flatten().collect()));
+        }
+        let elapsed = started.elapsed();
+        let timed_out = statuses.iter().zip(timeouts).enumerate().find_map(
+            |(index, (status, timeout))| match (status, timeout) {
+                (None, Some(timeout)) if elapsed >= *timeout => Some((index, *timeout)),
+                _ => None,
+            },
+        );
+        let waited = match timed_out {
+            _ if interrupted() => Waited::Interrupted,
+            Some((index, timeout)) => Waited::TimedOut(index, timeout),
+            None => {
+                thread::sleep(poll_interval);
+                poll_interval = (poll_interval * 2).min(MAX_POLL_INTERVAL);
+                continue;
+            }
+        };
+        for child in children.iter_mut() {
+            kill(child);
+        }
+        for child in children.iter_mut() {
+            child.wait()?;
+        }
+        return Ok(waited);
+    }
+}
+
+/// Kills `child`'s whole process group, so helpers it started don't keep the GPU busy
+#[cfg(unix)]
+fn kill(child: &mut Child) {
+    let Ok(process_group) = libc::pid_t::try_from(child.id()) else {
+        return;
+    };
+    // SAFETY: only sends a signal. The group was created by `spawn` and can't have been reused
+    // yet, because `child` hasn't been waited on.
+    unsafe {
+        libc::kill(-process_group, libc::SIGKILL);
+    }
+}
+
+#[cfg(not(unix))]
+fn kill(child: &mut Child) {
+    // the child may have exited already
+    let _ = child.kill();
+}
+
+fn timed_out(timeout: Duration) -> String {
+    format!("timed out after {timeout:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn failure_has_stderr_tail() {
+        let requirements = Requirements {
+            args: "-c 'seq 1 20 >&2; exit 3'",
+            current_dir: env::current_dir().unwrap(),
+        };
+        let error = run("sh", requirements, Program::Ffmpeg).unwrap_err();
+        let Error::FfmpegCommand(failure) = error else {
+            panic!("unexpected error: {error:?}");
+        };
+        assert_eq!(failure.command, "sh -c 'seq 1 20 >&2; exit 3'");
+        assert_eq!(failure.status, "exit status: 3");
+        let expected = (11..=20).map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(failure.stderr_tail, expected.join("\n"));
+    }
+
+    #[test]
+    fn stderr_spool() {
+        let path = env::temp_dir().join(format!("more-fps-stderr-{}.log", process::id()));
+        let mut stderr = Stderr {
+            tail: VecDeque::new(),
+            spool: Some((path.clone(), BufWriter::new(File::create(&path).unwrap()))),
+        };
+        for line in 1..=1000 {
+            stderr.push(&line.to_string());
+        }
+        let expected = (991..=1000).map(|i| i.to_string()).collect::<Vec<_>>();
+        assert_eq!(stderr.tail(), expected.join("\n"));
+
+        let mut logged = vec![];
+        stderr.copy_to(&mut logged).unwrap();
+        let logged = String::from_utf8(logged).unwrap();
+        assert_eq!(logged.lines().count(), 1000);
+        assert!(logged.starts_with("1\n2\n"));
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn piped_failure() {
+        let commands = vec![
+            PipedCommand {
+                binary: String::from("echo"),
+                args: String::from("frames"),
+                program: Program::Ffmpeg,
+            },
+            PipedCommand {
+                binary: String::from("sh"),
+                args: String::from("-c 'cat >/dev/null; echo broken >&2; exit 1'"),
+                program: Program::AI,
+            },
+        ];
+        let error = run_piped(commands).unwrap_err();
+        assert!(matches!(error, Error::AICommand(_)));
+        assert!(error.to_string().ends_with("(exit status: 1)\nbroken"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn piped_timeout() {
+        let commands = vec![
+            PipedCommand {
+                binary: String::from("echo"),
+                args: String::from("frames"),
+                program: Program::Ffmpeg,
+            },
+            PipedCommand {
+                binary: String::from("sh"),
+                args: String::from("-c 'sleep 30'"),
+                program: Program::AI,
+            },
+            PipedCommand {
+                binary: String::from("cat"),
+                args: String::new(),
+                program: Program::Ffmpeg,
+            },
+        ];
+        // only the ai binary has a timeout
+        let timeouts = [None, Some(Duration::from_millis(200)), None];
+        let started = Instant::now();
+        let error = run_piped_with_timeouts(commands, &timeouts).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let Error::AICommand(failure) = error else {
+            panic!("unexpected error: {error:?}");
+        };
+        assert_eq!(failure.status, "timed out after 200ms");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_process_group() {
+        let requirements = Requirements {
+            // the background sleep keeps stdout open unless the whole group is killed
+            args: "-c 'sleep 30 & sleep 30'",
+            current_dir: env::current_dir().unwrap(),
+        };
+        let started = Instant::now();
+        let timeout = Some(Duration::from_millis(200));
+        let error =
+            run_with_timeout("sh", requirements, Program::AI, timeout, |_| {}, |_| {}).unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(10));
+        let Error::AICommand(failure) = error else {
+            panic!("unexpected error: {error:?}");
+        };
+        assert_eq!(failure.status, "timed out after 200ms");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn lines() {
+        let requirements = Requirements {
+            args: "-c 'echo first; echo warning >&2; printf \"last\"'",
+            current_dir: env::current_dir().unwrap(),
+        };
+        let mut stdout = vec![];
+        let mut stderr = vec![];
+        run_lines(
+            "sh",
+            requirements,
+            Program::Ffmpeg,
+            |line| stdout.push(line.to_owned()),
+            |line| stderr.push(line.to_owned()),
+        )
+        .unwrap();
+        assert_eq!(stdout, ["first", "last"]);
+        assert_eq!(stderr, ["warning"]);
+    }
 }
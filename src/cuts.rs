@@ -0,0 +1,240 @@
+use crate::Error;
+use crate::FPS;
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use strum_macros::Display;
+
+/// Where the scene changes come from (`--cuts`)
+#[derive(ValueEnum, Copy, Clone, Debug, Display, PartialEq, Eq, Serialize, Deserialize)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum CutSource {
+    /// ffmpeg's scene detection, see -s
+    Detected,
+    /// The input's chapter markers
+    Chapters,
+    /// The timestamps in --cut-file
+    File,
+}
+
+/// A scene change we were told about instead of detecting it, so it's always kept
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cut {
+    /// frame number in the input
+    pub frame: usize,
+    pub source: CutSource,
+}
+
+impl Cut {
+    /// A line in the cut cache, so you can see where each chunk boundary came from
+    pub fn to_cache_line(&self) -> String {
+        format!("{} {}", self.frame, self.source)
+    }
+}
+
+/// What's in a --cut-file, based on its extension
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CutFileFormat {
+    /// One timestamp per line, `#` starts a comment
+    Plain,
+    /// The first column of each row, header rows are skipped
+    Csv,
+    /// The record in timecode of each event (CMX 3600), relative to the first event
+    Edl,
+}
+
+impl CutFileFormat {
+    fn of(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("csv") => Self::Csv,
+            Some("edl") => Self::Edl,
+            _ => Self::Plain,
+        }
+    }
+}
+
+/// The frames in a --cut-file
+/// Timestamps are seconds (`83.5`) or `hh:mm:ss.sss` (`00:01:23.5`)
+pub fn read_cut_file(path: &Path, source_fps: &FPS) -> Result<Vec<usize>, Error> {
+    let contents = fs::read_to_string(path)?;
+    parse_cuts(&contents, CutFileFormat::of(path), source_fps)
+        .map_err(|line| Error::InvalidCutFile(path.to_owned(), line))
+}
+
+/// Errors with the line that isn't a cut
+fn parse_cuts(
+    contents: &str,
+    format: CutFileFormat,
+    source_fps: &FPS,
+) -> Result<Vec<usize>, String> {
+    let frame_at = |line: &str, time: Option<Decimal>| {
+        time.and_then(|time| source_fps.frame_at(&time).ok())
+            .ok_or_else(|| line.to_owned())
+    };
+    let lines = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty());
+    match format {
+        CutFileFormat::Plain => lines
+            .filter(|line| !line.starts_with('#'))
+            .map(|line| {
+                let timestamp = line.split('#').next().unwrap_or_default().trim();
+                frame_at(line, parse_timestamp(timestamp))
+            })
+            .collect(),
+        CutFileFormat::Csv => {
+            let first_column = |line: &str| {
+                let column = line.split(',').next().unwrap_or_default();
+                parse_timestamp(column.trim().trim_matches('"'))
+            };
+            lines
+                .skip_while(|line| first_column(line).is_none())
+                .map(|line| frame_at(line, first_column(line)))
+                .collect()
+        }
+        CutFileFormat::Edl => {
+            // the record timecodes usually start at 01:00:00:00
+            let mut start = None;
+            let mut cuts = vec![];
+            for line in lines {
+                let fields = line.split_whitespace().collect::<Vec<_>>();
+                let is_event = fields.len() >= 8 && fields[0].chars().all(|c| c.is_ascii_digit());
+                // titles, comments, FCM, etc.
+                if !is_event {
+                    continue;
+                }
+                let record_in = parse_timecode(fields[fields.len() - 2], source_fps)
+                    .ok_or_else(|| line.to_owned())?;
+                let start = *start.get_or_insert(record_in);
+                cuts.push(record_in.saturating_sub(start));
+            }
+            Ok(cuts)
+        }
+    }
+}
+
+/// Seconds, `mm:ss` or `hh:mm:ss`, where the seconds can have decimals
+pub fn parse_timestamp(timestamp: &str) -> Option<Decimal> {
+    let mut parts = timestamp.rsplit(':');
+    let seconds = Decimal::from_str_exact(parts.next()?).ok()?;
+    let mut time = seconds;
+    for (part, multiplier) in parts.zip([60, 3600]) {
+        let part = part.parse::<u64>().ok()?;
+        time += Decimal::from(part) * Decimal::from(multiplier);
+    }
+    (!time.is_sign_negative() && timestamp.matches(':').count() <= 2).then_some(time)
+}
+
+/// The frame number of a `hh:mm:ss:ff` timecode, or `hh:mm:ss;ff` for drop frame
+fn parse_timecode(timecode: &str, source_fps: &FPS) -> Option<usize> {
+    let drop_frame = timecode.contains(';');
+    let parts = timecode
+        .split([':', ';'])
+        .map(|part| part.parse::<u64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    let [hours, minutes, seconds, frames] = parts[..] else {
+        return None;
+    };
+    // timecodes count 30 frames a second at 29.97 fps
+    let fps = source_fps
+        .numerator()
+        .get()
+        .div_ceil(source_fps.denominator().get());
+    let minutes = hours * 60 + minutes;
+    let mut frame = (minutes * 60 + seconds) * fps + frames;
+    if drop_frame {
+        // the first 2 frame numbers (at 30 fps) of every minute are skipped, except every 10th
+        frame -= fps / 15 * (minutes - minutes / 10);
+    }
+    usize::try_from(frame).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fps() -> FPS {
+        "24000/1001".parse().unwrap()
+    }
+
+    #[test]
+    fn timestamps() {
+        let timestamp = |s| parse_timestamp(s).map(|time| time.to_string());
+        assert_eq!(timestamp("83.5").as_deref(), Some("83.5"));
+        assert_eq!(timestamp("1:23.5").as_deref(), Some("83.5"));
+        assert_eq!(timestamp("01:01:23.500").as_deref(), Some("3683.500"));
+        assert_eq!(timestamp("-1"), None);
+        assert_eq!(timestamp("1:2:3:4"), None);
+        assert_eq!(timestamp("Timecode"), None);
+    }
+
+    #[test]
+    fn timecodes() {
+        assert_eq!(parse_timecode("00:00:01:12", &fps()), Some(36));
+        assert_eq!(parse_timecode("01:00:00:00", &fps()), Some(86400));
+        let ntsc = "30000/1001".parse().unwrap();
+        // 00:01:00;00 doesn't exist, so 00:01:00;02 is the frame after 00:00:59;29
+        assert_eq!(parse_timecode("00:00:59;29", &ntsc), Some(1799));
+        assert_eq!(parse_timecode("00:01:00;02", &ntsc), Some(1800));
+        assert_eq!(parse_timecode("00:10:00;00", &ntsc), Some(17982));
+        assert_eq!(parse_timecode("00:00:01", &fps()), None);
+    }
+
+    #[test]
+    fn plain() {
+        let contents = "# from the director\n10.01\n\n00:01:00.06 # car chase\n";
+        assert_eq!(
+            parse_cuts(contents, CutFileFormat::Plain, &fps()).unwrap(),
+            [240, 1440]
+        );
+        assert_eq!(
+            parse_cuts("10\nabc", CutFileFormat::Plain, &fps()).unwrap_err(),
+            "abc"
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let contents = "time,label\n\"10.01\",chase\n00:01:00.06,end\n";
+        assert_eq!(
+            parse_cuts(contents, CutFileFormat::Csv, &fps()).unwrap(),
+            [240, 1440]
+        );
+    }
+
+    #[test]
+    fn edl() {
+        let contents = "TITLE: Timeline 1
+FCM: NON-DROP FRAME
+
+001  AX       V     C        00:00:10:00 00:00:20:00 01:00:00:00 01:00:10:00
+* FROM CLIP NAME: a.mov
+
+002  AX       V     D    024 00:05:00:00 00:05:05:00 01:00:10:00 01:00:15:00
+003  AX       V     C        00:00:00:00 00:00:01:12 01:00:15:00 01:00:16:12
+";
+        assert_eq!(
+            parse_cuts(contents, CutFileFormat::Edl, &fps()).unwrap(),
+            [0, 240, 360]
+        );
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(CutFileFormat::of(Path::new("cuts.EDL")), CutFileFormat::Edl);
+        assert_eq!(CutFileFormat::of(Path::new("cuts.csv")), CutFileFormat::Csv);
+        assert_eq!(
+            CutFileFormat::of(Path::new("cuts.txt")),
+            CutFileFormat::Plain
+        );
+        assert_eq!(CutFileFormat::of(Path::new("cuts")), CutFileFormat::Plain);
+    }
+}
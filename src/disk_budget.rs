@@ -0,0 +1,188 @@
+use crate::command;
+use crate::ffmpeg::RawFormat;
+use crate::Error;
+use crate::FPS;
+use log::warn;
+use std::num::NonZeroU64;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for other chunks to free up space before giving up
+const SPACE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const SPACE_CHECKS: usize = 60;
+
+/// Estimates how much space the extracted + generated frames take in the temp dir
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DiskBudget {
+    /// `--max-temp-bytes`
+    pub max_bytes: Option<NonZeroU64>,
+    /// The size of one png frame
+    /// pngs are compressed, so using the uncompressed size errs on the safe side
+    pub frame_bytes: u64,
+}
+
+impl DiskBudget {
+    pub fn new(max_bytes: Option<NonZeroU64>, raw_format: &RawFormat) -> Self {
+        Self {
+            max_bytes,
+            frame_bytes: raw_format.frame_bytes(),
+        }
+    }
+
+    /// The space a chunk's extracted + generated frames take
+    pub fn chunk_bytes(&self, input_frames: NonZeroUsize, output_frames: NonZeroU64) -> u64 {
+        (input_frames.get() as u64).saturating_add(output_frames.get()) * self.frame_bytes
+    }
+
+    /// The most frames a chunk can have so that `chunks_in_flight` chunks (and their generated
+    /// frames) fit in `max_bytes`
+    /// `None` if there's no `max_bytes`
+    pub fn max_chunk_frames(
+        &self,
+        source_fps: FPS,
+        fps: FPS,
+        chunks_in_flight: NonZeroUsize,
+    ) -> Result<Option<NonZeroUsize>, Error> {
+        let Some(max_bytes) = self.max_bytes else {
+            return Ok(None);
+        };
+        // each extracted frame turns into fps / source_fps generated frames
+        // (a / b) + (c / d) = (ad + cb) / bd
+        let a = u128::from(source_fps.numerator().get());
+        let b = u128::from(source_fps.denominator().get());
+        let c = u128::from(fps.numerator().get());
+        let d = u128::from(fps.denominator().get());
+        let frames_per_input = (a * d + c * b, a * d);
+
+        let bytes_per_input = u128::from(self.frame_bytes) * frames_per_input.0;
+        let max_frames = u128::from(max_bytes.get()) * frames_per_input.1
+            / (bytes_per_input * chunks_in_flight.get() as u128).max(1);
+        let max_frames = usize::try_from(max_frames).unwrap_or(usize::MAX);
+        NonZeroUsize::new(max_frames).map(Some).ok_or_else(|| {
+            let bytes_per_input = bytes_per_input / frames_per_input.1;
+            Error::TempBudgetTooSmall(
+                max_bytes.get(),
+                u64::try_from(bytes_per_input).unwrap_or(u64::MAX),
+            )
+        })
+    }
+
+    /// Waits until `dir`'s filesystem has `needed` bytes available
+    /// Other chunks finishing can free up space, so this only fails after a while
+    pub fn wait_for_space(&self, dir: &Path, needed: u64) -> Result<(), Error> {
+        let mut available = fs2::available_space(dir)?;
+        for _ in 0..SPACE_CHECKS {
+            if available >= needed {
+                return Ok(());
+            }
+            if command::interrupted() {
+                return Err(Error::Interrupted);
+            }
+            warn!("Waiting for {needed} bytes of free space in {dir:?} ({available} available)");
+            thread::sleep(SPACE_CHECK_INTERVAL);
+            available = fs2::available_space(dir)?;
+        }
+        if available >= needed {
+            return Ok(());
+        }
+        Err(Error::NotEnoughSpace(needed, available))
+    }
+}
+
+/// Parses a number of bytes with an optional K, M, G or T suffix (powers of 1024)
+pub fn parse_bytes(bytes: &str) -> Result<NonZeroU64, String> {
+    let bytes = bytes.trim();
+    let (number, multiplier) = match bytes.char_indices().last() {
+        Some((index, suffix)) if suffix.is_ascii_alphabetic() => {
+            let power = match suffix.to_ascii_uppercase() {
+                'K' => 1,
+                'M' => 2,
+                'G' => 3,
+                'T' => 4,
+                _ => return Err(format!("unknown suffix {suffix:?}, expected K, M, G or T")),
+            };
+            (&bytes[..index], 1024_u64.pow(power))
+        }
+        _ => (bytes, 1),
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .and_then(NonZeroU64::new)
+        .ok_or_else(|| format!("should be a positive number of bytes: {bytes}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FrameAccountant;
+    use crate::Pipeline;
+
+    fn budget(max_bytes: u64) -> DiskBudget {
+        let raw_format = RawFormat {
+            width: 1920,
+            height: 1080,
+            pix_fmt: "rgb24",
+        };
+        DiskBudget::new(NonZeroU64::new(max_bytes), &raw_format)
+    }
+
+    #[test]
+    fn max_chunk_frames() {
+        let one = NonZeroUsize::new(1).unwrap();
+        let frame_bytes = 1920 * 1080 * 3;
+        let source_fps = FPS::try_new(24, 1).unwrap();
+        let fps = FPS::try_new(60, 1).unwrap();
+        // each input frame needs 1 + 2.5 frames
+        let actual = budget(frame_bytes * 35)
+            .max_chunk_frames(source_fps, fps, one)
+            .unwrap();
+        assert_eq!(actual, NonZeroUsize::new(10));
+
+        let actual = budget(frame_bytes * 35)
+            .max_chunk_frames(source_fps, fps, NonZeroUsize::new(2).unwrap())
+            .unwrap();
+        assert_eq!(actual, NonZeroUsize::new(5));
+
+        assert!(budget(frame_bytes)
+            .max_chunk_frames(source_fps, fps, one)
+            .is_err());
+        assert_eq!(
+            budget(0).max_chunk_frames(source_fps, fps, one).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn chunks_fit_in_the_pipeline() {
+        let max_bytes = 20 * 1024 * 1024 * 1024;
+        let budget = budget(max_bytes);
+        let source_fps = FPS::try_new(24000, 1001).unwrap();
+        let fps = FPS::try_new(60, 1).unwrap();
+        for workers in 1..=4 {
+            let chunks_in_flight = Pipeline::chunks_in_flight(workers);
+            let input_frames = budget
+                .max_chunk_frames(source_fps, fps, chunks_in_flight)
+                .unwrap()
+                .unwrap();
+            let output_frames =
+                FrameAccountant::new(fps, source_fps, 0).expected_at(input_frames.get());
+            let chunk_bytes =
+                budget.chunk_bytes(input_frames, NonZeroU64::new(output_frames).unwrap());
+            assert!(chunk_bytes * chunks_in_flight.get() as u64 <= max_bytes);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        assert_eq!(parse_bytes("1500").unwrap().get(), 1500);
+        assert_eq!(parse_bytes("20G").unwrap().get(), 20 * 1024 * 1024 * 1024);
+        assert_eq!(parse_bytes("512 m").unwrap().get(), 512 * 1024 * 1024);
+        assert!(parse_bytes("0").is_err());
+        assert!(parse_bytes("12X").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde::Serialize;
+use std::num::NonZeroUsize;
+use strum_macros::Display;
+
+/// Video encoders ffmpeg can use for each chunk
+#[derive(
+    ValueEnum, Copy, Clone, Debug, Default, Display, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    /// H.264
+    #[default]
+    Libx264,
+    /// H.265/HEVC
+    Libx265,
+    /// AV1
+    Libsvtav1,
+    /// VP9
+    #[value(name = "libvpx-vp9")]
+    #[strum(serialize = "libvpx-vp9")]
+    #[serde(rename = "libvpx-vp9")]
+    LibvpxVp9,
+    /// Lossless, so --crf and --preset are ignored
+    Ffv1,
+}
+
+impl Codec {
+    /// Whether a container (file extension) can hold this codec
+    /// mkv can hold anything, so it's what we fall back to
+    pub fn supports_container(&self, extension: &str) -> bool {
+        match extension.to_lowercase().as_str() {
+            "mkv" => true,
+            "webm" => matches!(self, Self::LibvpxVp9 | Self::Libsvtav1),
+            "mp4" | "m4v" => !matches!(self, Self::Ffv1),
+            _ => matches!(self, Self::Libx264),
+        }
+    }
+
+    /// `preferred` if it can hold this codec, otherwise mkv
+    pub fn container<'a>(&self, preferred: &'a str) -> &'a str {
+        if self.supports_container(preferred) {
+            preferred
+        } else {
+            "mkv"
+        }
+    }
+}
+
+/// x264's preset names, which get translated for the other encoders
+/// https://trac.ffmpeg.org/wiki/Encode/H.264#Preset
+#[derive(ValueEnum, Copy, Clone, Debug, Display, PartialEq, Eq, Serialize, Deserialize)]
+#[strum(serialize_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    Ultrafast,
+    Superfast,
+    Veryfast,
+    Faster,
+    Fast,
+    Medium,
+    Slow,
+    Slower,
+    Veryslow,
+}
+
+impl Preset {
+    /// svt-av1 uses 0 (slowest) to 13 (fastest)
+    fn svtav1(&self) -> usize {
+        match self {
+            Self::Ultrafast => 12,
+            Self::Superfast => 11,
+            Self::Veryfast => 10,
+            Self::Faster => 9,
+            Self::Fast => 8,
+            Self::Medium => 6,
+            Self::Slow => 4,
+            Self::Slower => 3,
+            Self::Veryslow => 2,
+        }
+    }
+
+    /// libvpx-vp9 uses -cpu-used 0 (slowest) to 5 (fastest) with -deadline good
+    fn vp9_cpu_used(&self) -> usize {
+        match self {
+            Self::Ultrafast | Self::Superfast => 5,
+            Self::Veryfast | Self::Faster => 4,
+            Self::Fast => 3,
+            Self::Medium => 2,
+            Self::Slow => 1,
+            Self::Slower | Self::Veryslow => 0,
+        }
+    }
+}
+
+/// Codecs that can encode chunks without losing any quality
+/// The concatenated chunks are then encoded once with the user's `Encoder`
+#[derive(ValueEnum, Copy, Clone, Debug, Display, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum LosslessCodec {
+    /// Smaller files, but always stored as mkv
+    Ffv1,
+    /// libx264 with -qp 0, faster to decode
+    Libx264,
+}
+
+impl LosslessCodec {
+    /// Keeping the `pix_fmt` of the final encode so the pixels are only converted once
+    pub fn encoder(&self, pix_fmt: &str) -> Encoder {
+        let (codec, preset) = match self {
+            Self::Ffv1 => (Codec::Ffv1, None),
+            Self::Libx264 => (Codec::Libx264, Some(Preset::Ultrafast)),
+        };
+        Encoder {
+            codec,
+            preset,
+            crf: NonZeroUsize::new(1).unwrap(),
+            lossless: true,
+            pix_fmt: pix_fmt.to_owned(),
+            extra_args: String::new(),
+        }
+    }
+}
+
+/// Everything ffmpeg needs to know to encode a video
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Encoder {
+    pub codec: Codec,
+    /// `None` uses the encoder's default
+    #[serde(deserialize_with = "crate::manifest::required")]
+    pub preset: Option<Preset>,
+    pub crf: NonZeroUsize,
+    /// Ignores the `crf` (only for libx264, ffv1 is always lossless)
+    pub lossless: bool,
+    pub pix_fmt: String,
+    /// Passed to ffmpeg as is, after everything else
+    pub extra_args: String,
+}
+
+impl Encoder {
+    /// The ffmpeg output options for this encoder
+    pub fn args(&self) -> String {
+        let mut args = vec![format!("-c:v {}", self.codec)];
+        match (self.codec, self.preset) {
+            (Codec::Libx264 | Codec::Libx265, Some(preset)) => {
+                args.push(format!("-preset {preset}"))
+            }
+            (Codec::Libsvtav1, Some(preset)) => args.push(format!("-preset {}", preset.svtav1())),
+            (Codec::LibvpxVp9, Some(preset)) => args.push(format!(
+                "-deadline good -cpu-used {}",
+                preset.vp9_cpu_used()
+            )),
+            _ => (),
+        }
+        match (self.codec, self.lossless) {
+            (Codec::Ffv1, _) => (),
+            (Codec::Libx264, true) => args.push(String::from("-qp 0")),
+            (Codec::Libx264 | Codec::Libx265 | Codec::Libsvtav1, _) => {
+                args.push(format!("-crf {}", self.crf))
+            }
+            // constant quality mode: https://trac.ffmpeg.org/wiki/Encode/VP9
+            (Codec::LibvpxVp9, _) => args.push(format!("-crf {} -b:v 0", self.crf)),
+        }
+        args.push(format!("-pix_fmt {}", self.pix_fmt));
+        if !self.extra_args.trim().is_empty() {
+            args.push(self.extra_args.trim().to_owned());
+        }
+        args.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoder(codec: Codec, preset: Option<Preset>) -> Encoder {
+        Encoder {
+            codec,
+            preset,
+            crf: NonZeroUsize::new(18).unwrap(),
+            lossless: false,
+            pix_fmt: String::from("yuv420p10le"),
+            extra_args: String::new(),
+        }
+    }
+
+    #[test]
+    fn x264_args() {
+        let actual = encoder(Codec::Libx264, None).args();
+        assert_eq!(actual, "-c:v libx264 -crf 18 -pix_fmt yuv420p10le");
+    }
+
+    #[test]
+    fn x265_preset() {
+        let mut encoder = encoder(Codec::Libx265, Some(Preset::Veryslow));
+        encoder.extra_args = String::from("-x265-params aq-mode=3");
+        assert_eq!(
+            encoder.args(),
+            "-c:v libx265 -preset veryslow -crf 18 -pix_fmt yuv420p10le -x265-params aq-mode=3"
+        );
+    }
+
+    #[test]
+    fn translated_presets() {
+        let actual = encoder(Codec::Libsvtav1, Some(Preset::Medium)).args();
+        assert_eq!(
+            actual,
+            "-c:v libsvtav1 -preset 6 -crf 18 -pix_fmt yuv420p10le"
+        );
+        let actual = encoder(Codec::LibvpxVp9, Some(Preset::Fast)).args();
+        assert_eq!(
+            actual,
+            "-c:v libvpx-vp9 -deadline good -cpu-used 3 -crf 18 -b:v 0 -pix_fmt yuv420p10le"
+        );
+    }
+
+    #[test]
+    fn ffv1_is_lossless() {
+        let actual = encoder(Codec::Ffv1, Some(Preset::Slow)).args();
+        assert_eq!(actual, "-c:v ffv1 -pix_fmt yuv420p10le");
+    }
+
+    #[test]
+    fn lossless_chunks() {
+        let actual = LosslessCodec::Libx264.encoder("yuv420p").args();
+        assert_eq!(
+            actual,
+            "-c:v libx264 -preset ultrafast -qp 0 -pix_fmt yuv420p"
+        );
+        let actual = LosslessCodec::Ffv1.encoder("yuv420p10le").args();
+        assert_eq!(actual, "-c:v ffv1 -pix_fmt yuv420p10le");
+    }
+
+    #[test]
+    fn containers() {
+        assert_eq!(Codec::Libx264.container("mp4"), "mp4");
+        assert_eq!(Codec::Ffv1.container("mp4"), "mkv");
+        assert_eq!(Codec::LibvpxVp9.container("webm"), "webm");
+        assert_eq!(Codec::Libx265.container("webm"), "mkv");
+        assert!(Codec::Ffv1.supports_container("MKV"));
+    }
+}
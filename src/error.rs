@@ -1,3 +1,6 @@
+use crate::command::CommandFailure;
+use crate::encoder::Codec;
+use crate::interpolator::Backend;
 use rust_decimal::Decimal;
 use std::ffi::OsString;
 use std::io;
@@ -9,26 +12,82 @@ use thiserror::Error;
 pub enum Error {
     #[error("IO operation failed: {0:?}")]
     Io(#[from] io::Error),
-    #[error("Failed to execute Ffprobe")]
-    FfprobeCommand,
-    #[error("Failed to execute ffmpeg")]
-    FfmpegCommand,
-    #[error("Failed to execute AI command")]
-    AICommand,
+    #[error("Failed to execute ffprobe: {0}")]
+    FfprobeCommand(CommandFailure),
+    #[error("Failed to execute ffmpeg: {0}")]
+    FfmpegCommand(CommandFailure),
+    #[error("Failed to execute AI command: {0}")]
+    AICommand(CommandFailure),
+    #[error("The {0} backend needs an --ai-template")]
+    MissingTemplate(Backend),
+    #[error("The {0} backend needs AI_BINARY and AI_MODEL")]
+    MissingAiBinary(Backend),
+    #[error("{0} can't be used with this target: {1}")]
+    UnsupportedTarget(String, String),
+    #[error("{0} can't stream frames over stdin/stdout")]
+    StreamingUnsupported(String),
     #[error("Command parse failure: {0}")]
     ParseError(#[from] shell_words::ParseError),
+    #[error("Json error: {0}")]
+    Json(#[from] serde_json::Error),
     #[error("Decimal error: {0}")]
     Decimal(#[from] rust_decimal::Error),
-    #[error("Unable to create scene time ranges:\nstart {0}\nmax_step_size {1}\nend {2}")]
-    UnableToCreateTimeRanges(Decimal, NonZeroUsize, Decimal),
-    #[error("Unable to calculate expected frame count from FPS: {0}")]
-    BadFPS(NonZeroUsize),
+    #[error(
+        "Unable to create scene time ranges (in frames):\nstart {0}\nmax_step_size {1}\nend {2}"
+    )]
+    UnableToCreateTimeRanges(usize, NonZeroUsize, usize),
+    #[error(
+        "Invalid line in the scene cache, run with -r everything to find the scenes again: {0}"
+    )]
+    InvalidSceneCache(String),
+    #[error("Unable to read a cut from {0:?}: {1}")]
+    InvalidCutFile(PathBuf, String),
+    #[error("--cuts file needs a --cut-file")]
+    MissingCutFile,
+    #[error("--start, --end or --segments don't include any of the input")]
+    NothingToProcess,
+    #[error(
+        "There's nothing to rerender in {0:?}, run without rerender first (with --keep-temp-dir)"
+    )]
+    NothingToRerender(PathBuf),
+    #[error("{0:?} changed since its chunks were created, so they can't be rerendered")]
+    InputChanged(PathBuf),
+    #[error(
+        "The chunks were created with a different {0}, only the interpolator's settings can change for a rerender"
+    )]
+    RerenderConflict(&'static str),
+    #[error("There's no chunk {0}, the video was split into {1} chunks")]
+    InvalidChunk(usize, usize),
+    #[error("None of the chunks have {0} seconds of the input in them")]
+    NoChunkAt(Decimal),
+    #[error("Invalid FPS, expected a number like 60 or a ratio like 60000/1001: {0}")]
+    InvalidFPS(String),
     #[error("Multiplcation overflow: {0} * {1}")]
     MultiplicationOverflow(String, String),
+    #[error("Frame count should be a whole, positive number: {0}")]
+    InvalidFrameCount(Decimal),
+    #[error("Unexpected output from command: {0}")]
+    UnexpectedOutput(String),
+    #[error("{0} videos can't be stored in a .{1} file, try .mkv instead")]
+    UnsupportedContainer(Codec, String),
+    #[error("--outside original makes a variable frame rate video, which can't be stored in a .{0} file, try .mkv instead")]
+    VariableFrameRateContainer(String),
     #[error("Missing extension for file: {0}")]
     MissingExtension(PathBuf),
     #[error("Invalid Unicode: {0:?}")]
     InvalidUnicode(OsString),
     #[error("Unable to read dir: {0:?}")]
     ReadDir(PathBuf),
+    #[error("Video for chunk {0} is missing: {1:?}")]
+    MissingChunk(usize, PathBuf),
+    #[error("The ai binary failed on chunks {0:?}, run again with -r nothing to retry them")]
+    FailedChunks(Vec<usize>),
+    #[error("The ai binary failed on chunks {0:?}, so their old videos were kept")]
+    FailedRerender(Vec<usize>),
+    #[error("Interrupted, run again with -r nothing to continue where you left off")]
+    Interrupted,
+    #[error("Not enough free space in the temp dir: {0} bytes needed, {1} bytes available")]
+    NotEnoughSpace(u64, u64),
+    #[error("--max-temp-bytes {0} is too small, each extracted frame (+ the frames generated from it) needs {1} bytes")]
+    TempBudgetTooSmall(u64, u64),
 }
@@ -1,46 +1,158 @@
 use crate::command;
+use crate::command::PipedCommand;
+use crate::command::Program;
+use crate::cuts;
+use crate::cuts::Cut;
+use crate::cuts::CutSource;
+use crate::encoder::Encoder;
+use crate::scenes::SceneDetection;
+use crate::scenes::SceneScore;
+use crate::scenes::Scenes;
+use crate::window;
+use crate::window::Window;
 use crate::Error;
+use crate::Interpolator;
 use crate::TimeRange;
-use crate::TimeRanges;
+use crate::FPS;
 use log::debug;
-use regex::Regex;
+use log::info;
+use log::warn;
 use rust_decimal::Decimal;
 use std::fs;
 use std::io;
 use std::io::Write;
+use std::num::NonZeroU64;
 use std::num::NonZeroUsize;
+use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
 
 pub fn ffmpeg<T: AsRef<str>>(requirements: T) -> Result<String, Error> {
-    command::run(
+    command::run("ffmpeg", requirements.as_ref().try_into()?, Program::Ffmpeg)
+}
+
+/// Runs ffmpeg with `-progress`, calling `on_progress` every time ffmpeg reports how far along
+/// it is (about twice a second)
+pub fn ffmpeg_with_progress<T: AsRef<str>>(
+    requirements: T,
+    mut on_progress: impl FnMut(&FfmpegProgress) + Send,
+) -> Result<(), Error> {
+    let args = format!("-progress pipe:1 -nostats {}", requirements.as_ref());
+    let mut progress = FfmpegProgress::default();
+    command::run_lines(
         "ffmpeg",
-        requirements.as_ref().try_into()?,
-        Error::FfmpegCommand,
+        args.as_str().try_into()?,
+        Program::Ffmpeg,
+        |line| {
+            if progress.update(line) {
+                on_progress(&progress);
+            }
+        },
+        |_| {},
     )
 }
 
+/// What ffmpeg's `-progress` output says so far
+/// ffmpeg writes a block of `key=value` lines at a time, ending with a `progress` line
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FfmpegProgress {
+    /// frames written
+    pub frame: u64,
+    /// of the output, `None` until ffmpeg knows
+    pub out_time: Option<Decimal>,
+    /// e.g. `1.5x`
+    pub speed: Option<String>,
+    /// ffmpeg is done
+    pub end: bool,
+}
+
+impl FfmpegProgress {
+    /// Returns true when `line` ends a block
+    /// Values that can't be parsed are ignored, progress isn't worth failing over
+    fn update(&mut self, line: &str) -> bool {
+        let Some((key, value)) = line.split_once('=') else {
+            return false;
+        };
+        let value = value.trim();
+        match key.trim() {
+            "frame" => self.frame = value.parse().unwrap_or(self.frame),
+            "out_time_us" => {
+                self.out_time = value
+                    .parse::<i64>()
+                    .ok()
+                    .map(|micros| Decimal::new(micros, 6).normalize())
+            }
+            "speed" => self.speed = (value != "N/A").then(|| value.to_owned()),
+            "progress" => {
+                self.end = value == "end";
+                return true;
+            }
+            _ => {}
+        }
+        false
+    }
+}
+
+/// How often `aggregate` logs how far along it is
+const PROGRESS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// For `ffmpeg_with_progress`, logs how far along `what` is every `PROGRESS_LOG_INTERVAL`
+fn log_progress(what: &str) -> impl FnMut(&FfmpegProgress) + Send + '_ {
+    let mut last_logged = Instant::now();
+    move |progress| {
+        if progress.end || last_logged.elapsed() < PROGRESS_LOG_INTERVAL {
+            return;
+        }
+        last_logged = Instant::now();
+        let out_time = progress
+            .out_time
+            .map_or(String::from("-"), |out_time| format!("{out_time:.1}s"));
+        let speed = progress.speed.as_deref().unwrap_or("-");
+        info!(
+            "{what}: {out_time} ({} frames) written, {speed} speed",
+            progress.frame
+        );
+    }
+}
+
+/// The scores of the frames in a window (`None` is the whole input)
+type Scan = (Option<Window>, Vec<SceneScore>);
+
 #[derive(Debug)]
 pub struct FfmpegStepper {
     input_file: PathBuf,
     frames_dir: PathBuf,
     videos_dir: PathBuf,
     scene_file: PathBuf,
+    /// the cuts that didn't come from the `scene_file`, see `Cut`
+    cut_file: PathBuf,
     /// text file
     concat_file: PathBuf,
     /// the video file generated from concatting the videos_dir
     video_file: PathBuf,
-    crf: NonZeroUsize,
-    fps: NonZeroUsize,
-    input_extension: String,
+    /// used for each chunk
+    chunk_encoder: Encoder,
+    /// if the chunks are lossless, this is used to encode them all at once in `aggregate`
+    /// otherwise the chunks are stream copied
+    final_encoder: Option<Encoder>,
+    /// frame rate of the videos we create
+    fps: FPS,
+    /// frame rate of the `input_file`
+    source_fps: FPS,
+    /// the container of each chunk, which has to be able to hold the `encoder`'s codec
+    chunk_extension: String,
 }
 
 impl FfmpegStepper {
     pub fn try_new(
         temp_dir: &Path,
         input_file: PathBuf,
-        crf: NonZeroUsize,
-        fps: NonZeroUsize,
+        chunk_encoder: Encoder,
+        final_encoder: Option<Encoder>,
+        fps: FPS,
+        source_fps: FPS,
     ) -> Result<Self, Error> {
         let frames_dir = temp_dir.join("frames");
         dir_exists_or_create(&frames_dir)?;
@@ -48,22 +160,27 @@ impl FfmpegStepper {
         let videos_dir = temp_dir.join("videos");
         dir_exists_or_create(&videos_dir)?;
 
-        let input_extension = get_extension(&input_file)?.to_owned();
+        let input_extension = get_extension(&input_file)?;
+        let chunk_extension = chunk_encoder.codec.container(input_extension).to_owned();
 
-        let scene_file = temp_dir.join("scene_timestamps.txt");
+        let scene_file = temp_dir.join("scene_scores.txt");
+        let cut_file = temp_dir.join("cuts.txt");
         let concat_file = temp_dir.join("concat.txt");
-        let video_file = temp_dir.join(format!("video.{input_extension}"));
+        let video_file = temp_dir.join(format!("video.{chunk_extension}"));
 
         Ok(Self {
             input_file,
             frames_dir,
             scene_file,
+            cut_file,
             concat_file,
             video_file,
             videos_dir,
-            crf,
+            chunk_encoder,
+            final_encoder,
             fps,
-            input_extension,
+            source_fps,
+            chunk_extension,
         })
     }
 
@@ -71,44 +188,212 @@ impl FfmpegStepper {
         &self.frames_dir
     }
 
-    /// Returns the number of video pieces we've already extracted
-    pub fn existing_video_count(&self) -> Result<usize, Error> {
-        let count = fs::read_dir(&self.videos_dir)
-            .map_err(|_| Error::ReadDir(self.videos_dir.to_path_buf()))?
-            .count();
-        Ok(count)
+    /// Where the video for chunk `video_number` goes
+    fn video_path(&self, video_number: usize) -> PathBuf {
+        self.videos_dir
+            .join(format!("{video_number}.{}", self.chunk_extension))
     }
 
-    /// Returns a frame extractor which can be used to extract frames
-    // todo if scene_file doesn't exist yet, we should clear the video_dir
-    pub fn flattened_time_ranges(
+    /// Checks if a previous run already created the video for chunk `video_number` with
+    /// `expected_frames` frames at `frame_rate` (ours, unless it's `Treatment::Original`)
+    /// Cancelling at the wrong time can leave a truncated (or unreadable) video behind, which
+    /// isn't valid (see `remove_video`)
+    pub fn is_valid_video(
         &self,
-        max_step_size: NonZeroUsize,
-        scene_gt: &str,
-    ) -> Result<Vec<TimeRange>, Error> {
-        let mut timestamps: Vec<Decimal> = vec![Decimal::ZERO];
-        timestamps.append(&mut find_scene_timestamps(
-            &self.input_file,
-            scene_gt,
-            &self.scene_file,
-        )?);
-        let time_ranges = timestamps
-            .as_slice()
-            .windows(2)
-            .map(|pair| (pair[0], pair[1]))
-            .map(|(start, end)| {
-                TimeRanges::try_new(start, max_step_size, end)
-                    .ok_or(Error::UnableToCreateTimeRanges(start, max_step_size, end))
+        video_number: usize,
+        expected_frames: u64,
+        frame_rate: &FPS,
+    ) -> bool {
+        let video_path = self.video_path(video_number);
+        if !video_path.exists() {
+            return false;
+        }
+        let expected_duration = frame_rate.time_of(expected_frames as usize);
+        let tolerance = frame_rate.time_of(1);
+        match probe_video(&video_path) {
+            Ok(probe) => {
+                let valid = probe.frame_count == expected_frames
+                    && (probe.duration - expected_duration).abs() <= tolerance;
+                if !valid {
+                    warn!(
+                        "{video_path:?} has {} frames ({} seconds), expected {expected_frames} frames ({} seconds)",
+                        probe.frame_count,
+                        probe.duration,
+                        expected_duration.round_dp(6)
+                    );
+                }
+                valid
+            }
+            Err(e) => {
+                warn!("Unable to probe {video_path:?}: {e}");
+                false
+            }
+        }
+    }
+
+    /// Deletes chunk `video_number`'s video (if there is one) so it's created again
+    pub fn remove_video(&self, video_number: usize) -> Result<(), Error> {
+        let video_path = self.video_path(video_number);
+        if video_path.exists() {
+            warn!("Deleting {video_path:?} so it can be created again");
+            fs::remove_file(&video_path)?;
+        }
+        Ok(())
+    }
+
+    /// Splits the input video into frame ranges at each scene change, from each of the
+    /// `sources` (`cut_file` is the --cut-file)
+    /// Only the frames in the `windows` are scanned for scene changes. When the rest of the
+    /// input is left out (`clip`), the ranges stop at the end of the last window, so the input
+    /// isn't read past it either.
+    /// Each range is at most `max_chunk_frames` (see `DiskBudget::max_chunk_frames`), even if
+    /// the `scene_detection` allows longer ones
+    pub fn scenes(
+        &self,
+        scene_detection: &SceneDetection,
+        sources: &[CutSource],
+        cut_file: Option<&Path>,
+        max_chunk_frames: Option<NonZeroUsize>,
+        windows: &[Window],
+        clip: bool,
+    ) -> Result<Scenes, Error> {
+        let mut cuts = vec![];
+        if sources.contains(&CutSource::Chapters) {
+            for time in chapter_times(&self.input_file)? {
+                cuts.push(Cut {
+                    frame: self.source_fps.frame_at(&time)?,
+                    source: CutSource::Chapters,
+                });
+            }
+        }
+        if sources.contains(&CutSource::File) {
+            let cut_file = cut_file.ok_or(Error::MissingCutFile)?;
+            for frame in cuts::read_cut_file(cut_file, &self.source_fps)? {
+                cuts.push(Cut {
+                    frame,
+                    source: CutSource::File,
+                });
+            }
+        }
+        let scans = match sources.contains(&CutSource::Detected) {
+            true => self.scan_windows(windows)?,
+            false => vec![],
+        };
+        let mut scene_detection = *scene_detection;
+        if let Some(max_chunk_frames) = max_chunk_frames {
+            scene_detection.max_frames = scene_detection.max_frames.min(max_chunk_frames);
+        }
+        let end = match clip && !windows.is_empty() && !scans.is_empty() {
+            true => self.scanned_end(&scans)?,
+            false => frame_count(&self.input_file)?,
+        };
+        let scores = scans
+            .into_iter()
+            .flat_map(|(_, scores)| scores)
+            .collect::<Vec<_>>();
+        let forced = cuts.iter().map(|cut| cut.frame).collect::<Vec<_>>();
+        let scenes = scene_detection.scenes(&scores, &forced, end)?;
+
+        let lines = cuts
+            .iter()
+            .map(Cut::to_cache_line)
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(&self.cut_file, lines)?;
+        debug!("Using {} cuts from {sources:?}", cuts.len());
+        Ok(scenes)
+    }
+
+    /// The scores of the frames in each of the `windows` (merged, so none are scanned twice),
+    /// or in the whole input if there aren't any
+    fn scan_windows(&self, windows: &[Window]) -> Result<Vec<Scan>, Error> {
+        if windows.is_empty() {
+            let scores =
+                find_scene_scores(&self.input_file, &self.source_fps, None, &self.scene_file)?;
+            return Ok(vec![(None, scores)]);
+        }
+        window::merged(windows)
+            .into_iter()
+            .map(|window| {
+                let scene_file = self.window_scene_file(&window);
+                let scores = find_scene_scores(
+                    &self.input_file,
+                    &self.source_fps,
+                    Some(&window),
+                    &scene_file,
+                )?;
+                Ok((Some(window), scores))
             })
-            .collect::<Result<Vec<TimeRanges>, _>>()?;
+            .collect()
+    }
+
+    /// The frame after the last one in the scanned windows
+    /// A window's scan stops at its end, or the input's if that comes first
+    fn scanned_end(&self, scans: &[Scan]) -> Result<NonZeroUsize, Error> {
+        let mut end = 0;
+        for (window, scores) in scans {
+            let Some(last) = scores.last() else {
+                // the window starts after the input ends
+                continue;
+            };
+            let scanned_end = last.frame + 1;
+            end = end.max(match window.and_then(|window| window.end) {
+                Some(window_end) => self.source_fps.frame_at(&window_end)?.min(scanned_end),
+                None => scanned_end,
+            });
+        }
+        NonZeroUsize::new(end).ok_or(Error::NothingToProcess)
+    }
 
-        let flattened_time_ranges = time_ranges
+    /// Where the scores of the frames in `window` are cached
+    fn window_scene_file(&self, window: &Window) -> PathBuf {
+        let end = window
+            .end
+            .map_or_else(|| String::from("end"), |end| end.normalize().to_string());
+        self.scene_file.with_file_name(format!(
+            "scene_scores_{}-{end}.txt",
+            window.start.normalize()
+        ))
+    }
+
+    /// The score of every frame in the `windows` (or the whole input), from the scene cache if
+    /// it's there
+    pub fn scene_scores(&self, windows: &[Window]) -> Result<Vec<SceneScore>, Error> {
+        Ok(self
+            .scan_windows(windows)?
             .into_iter()
-            .flatten()
-            // skipping because we already have existing videos
-            .collect();
+            .flat_map(|(_, scores)| scores)
+            .collect())
+    }
+
+    /// Deletes the cached scene scores (of the whole input and each window) so they're found
+    /// again
+    pub fn clear_scene_cache(&self) -> Result<(), Error> {
+        if let Some(temp_dir) = self.scene_file.parent() {
+            for entry in fs::read_dir(temp_dir)? {
+                let path = entry?.path();
+                let is_scene_file = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("scene_scores"));
+                if is_scene_file {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        if self.cut_file.exists() {
+            fs::remove_file(&self.cut_file)?;
+        }
+        Ok(())
+    }
 
-        Ok(flattened_time_ranges)
+    /// Deletes every chunk's video
+    pub fn clear_videos_dir(&self) -> Result<(), Error> {
+        if self.videos_dir.exists() {
+            fs::remove_dir_all(&self.videos_dir)?;
+            fs::create_dir_all(&self.videos_dir)?;
+        }
+        Ok(())
     }
 
     pub fn clear_frames_dir(&self) -> Result<(), Error> {
@@ -119,105 +404,245 @@ impl FfmpegStepper {
         Ok(())
     }
 
-    pub fn extract_frames(&self, time_range: &TimeRange) -> Result<&Path, Error> {
-        self.clear_frames_dir()?;
+    /// Extracts chunk `video_number`'s frames into its own directory in the `frames_dir`
+    pub fn extract_frames(
+        &self,
+        video_number: usize,
+        time_range: &TimeRange,
+    ) -> Result<PathBuf, Error> {
+        let output_dir = self.frames_dir.join(video_number.to_string());
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir)?;
+        }
+        fs::create_dir_all(&output_dir)?;
 
-        extract_frames(
-            &time_range.start,
-            &self.input_file,
-            &time_range.duration(),
-            &self.frames_dir,
-        )?;
-        Ok(&self.frames_dir)
+        extract_frames(time_range, &self.source_fps, &self.input_file, &output_dir)?;
+        Ok(output_dir)
     }
 
     /// Takes the extracted frames when calling `extract_frames` and creates a video in the
     /// `video_dir`
-    pub fn frames_to_video(&self, video_number: usize, input_dir: PathBuf) -> Result<(), Error> {
-        let video_path = self
-            .videos_dir
-            .join(format!("{video_number}.{}", self.input_extension));
-
-        // maybe this will work for windows?
-        #[cfg(target_os="windows")]
-        let args = format!("-y framerate {} -{} -pattern_type sequence -i %08d.png -crf {} -c:v libx264 -pix_fmt yuv420p {}",
-            self.fps,
-            self.crf,
-            video_path.display()
-        );
-        #[cfg(not(target_os="windows"))]
-        let args = format!("-y -framerate {} -pattern_type glob -i '*.png' -crf {} -c:v libx264 -pix_fmt yuv420p {}",
+    /// `glob` matches the frames in the `input_dir` (see `Interpolator::output_glob`)
+    pub fn frames_to_video(
+        &self,
+        video_number: usize,
+        input_dir: &Path,
+        glob: &str,
+    ) -> Result<(), Error> {
+        let video_path = self.video_path(video_number);
+        let args = format!(
+            "-y -framerate {} {} {} {}",
             self.fps,
-            self.crf,
-            video_path.display()
+            frames_input(input_dir, glob)?,
+            self.chunk_encoder.args(),
+            quote(&video_path)
         );
         let requirements = command::Requirements {
             args: &args,
-            current_dir: input_dir,
+            current_dir: input_dir.to_path_buf(),
+        };
+        command::run("ffmpeg", requirements, Program::Ffmpeg)?;
+        Ok(())
+    }
+
+    /// Creates the video for chunk `video_number` without writing any frames to disk
+    /// The raw frames are piped from the decoder, through the interpolator, into the encoder
+    /// Returns the number of frames in the video
+    pub fn stream_chunk(
+        &self,
+        video_number: usize,
+        time_range: &TimeRange,
+        interpolator: &dyn Interpolator,
+        raw_format: &RawFormat,
+        frame_count: NonZeroU64,
+    ) -> Result<u64, Error> {
+        let input_frames = time_range.frame_count().get();
+        let interpolate = interpolator
+            .stream_command(raw_format, input_frames, frame_count)
+            .ok_or_else(|| Error::StreamingUnsupported(interpolator.name()))?;
+        let video_path = self.video_path(video_number);
+        let decode = PipedCommand {
+            binary: String::from("ffmpeg"),
+            args: format!(
+                "{} {}",
+                extract_args(time_range, &self.source_fps, &self.input_file),
+                raw_format.output_args()
+            ),
+            program: Program::Ffmpeg,
+        };
+        let encode = PipedCommand {
+            binary: String::from("ffmpeg"),
+            args: format!(
+                "-y {} -framerate {} -i - {} {}",
+                raw_format.input_args(),
+                self.fps,
+                self.chunk_encoder.args(),
+                quote(&video_path)
+            ),
+            program: Program::Ffmpeg,
         };
-        command::run("ffmpeg", requirements, Error::FfmpegCommand)?;
+        command::run_piped(vec![decode, interpolate, encode])?;
+
+        // there are no files to count, so asking the video how many frames it ended up with
+        Ok(self::frame_count(&video_path)?.get() as u64)
+    }
+
+    /// Encodes chunk `video_number` straight from the input, without any new frames
+    /// The video keeps the input's frame rate (see `Treatment::Original`)
+    pub fn copy_chunk(&self, video_number: usize, time_range: &TimeRange) -> Result<u64, Error> {
+        let video_path = self.video_path(video_number);
+        let args = format!(
+            "-y {} {} {}",
+            extract_args(time_range, &self.source_fps, &self.input_file),
+            self.chunk_encoder.args(),
+            quote(&video_path)
+        );
+        ffmpeg(args)?;
+        Ok(time_range.frame_count().get() as u64)
+    }
+
+    /// Replaces chunk `video_number`'s video with the videos of the `pieces` put together
+    /// The pieces are chunk numbers past the last chunk, and are deleted afterwards
+    pub fn replace_video(&self, video_number: usize, pieces: &[usize]) -> Result<(), Error> {
+        let piece_files = pieces
+            .iter()
+            .map(|piece| self.video_path(*piece))
+            .collect::<Vec<_>>();
+        let video_path = self.video_path(video_number);
+        // the old video is kept until the new one is done
+        let new_video = video_path.with_extension(format!("new.{}", self.chunk_extension));
+        concat_videos(&self.concat_file, &piece_files, &new_video)?;
+        fs::rename(&new_video, &video_path)?;
+        for piece_file in piece_files {
+            fs::remove_file(piece_file)?;
+        }
         Ok(())
     }
+
+    /// The videos for chunks `0..chunk_count`, in order
+    /// Errors if any of them are missing, otherwise the final video would silently skip a scene
+    pub fn video_files(&self, chunk_count: usize) -> Result<Vec<PathBuf>, Error> {
+        (0..chunk_count)
+            .map(|video_number| {
+                let video_path = self.video_path(video_number);
+                if video_path.is_file() {
+                    Ok(video_path)
+                } else {
+                    Err(Error::MissingChunk(video_number, video_path))
+                }
+            })
+            .collect()
+    }
+
     /// When you're done extracting frames, call this function and we'll aggregate the
     /// videos + audio + subtitles into the output file provided
-    pub fn aggregate(&self, output_file: &Path) -> Result<(), Error> {
-        concat_videos(&self.concat_file, &self.videos_dir, &self.video_file)?;
+    /// `chunk_count` is the number of time ranges the video was split into
+    /// `clip` is the source frames the chunks came from, if they're only part of the input (see
+    /// `Outside::Clip`)
+    pub fn aggregate(
+        &self,
+        output_file: &Path,
+        chunk_count: usize,
+        clip: Option<&[Range<usize>]>,
+    ) -> Result<(), Error> {
+        let video_files = self.video_files(chunk_count)?;
+        concat_videos(&self.concat_file, &video_files, &self.video_file)?;
+
+        // encoding everything at once means consistent rate control/GOPs across chunk boundaries
+        let video_args = match &self.final_encoder {
+            Some(encoder) => encoder.args(),
+            None => String::from("-c:v copy"),
+        };
 
         // Need -max_interleave_delta:
         // https://trac.ffmpeg.org/ticket/6037
-        let args = format!("-ignore_unknown -y -i {} -vn -i {} -map 0 -c:v copy -map 1 -c:a copy -c:s copy -map_chapters 1 -max_interleave_delta 0 {}",
-            &self.video_file.display(),
-            &self.input_file.display(),
-            output_file.display()
+        let args = format!(
+            "-ignore_unknown -y -i {} -vn -i {} -map 0 {video_args} {} -max_interleave_delta 0 {}",
+            quote(&self.video_file),
+            quote(&self.input_file),
+            audio_args(clip, &self.source_fps),
+            quote(output_file)
         );
-        ffmpeg(args)?;
+        ffmpeg_with_progress(args, log_progress("Writing the output"))?;
         Ok(())
     }
 }
 
+/// How the audio, subtitles + chapters are copied from the input
+/// A clip only gets the audio of its frames, which means re-encoding it. The subtitles + chapters
+/// would be out of sync, so they're left out.
+fn audio_args(clip: Option<&[Range<usize>]>, source_fps: &FPS) -> String {
+    let Some(clip) = clip else {
+        return String::from("-map 1 -c:a copy -c:s copy -map_chapters 1");
+    };
+    let between = clip
+        .iter()
+        .map(|frames| {
+            format!(
+                r"between(t\,{}\,{})",
+                source_fps.time_of(frames.start).round_dp(6).normalize(),
+                source_fps.time_of(frames.end).round_dp(6).normalize()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("+");
+    // escaping the commas so they aren't taken as the end of the filter
+    let filter = format!("aselect={between},asetpts=N/SR/TB");
+    format!(
+        "-map 1:a? -af {} -map_chapters -1",
+        shell_words::quote(&filter)
+    )
+}
+
 /// Give a path to create the concat file for ffmpeg to reference
-/// This concat file will have all of the files in the videos_dir provided
+/// This concat file will have all of the `video_files` provided, in the same order
 /// Then use ffmpeg to concat the videos into the final output_file path
 pub fn concat_videos(
     concat_file_path: &Path,
-    videos_dir: &Path,
+    video_files: &[PathBuf],
     output_file: &Path,
 ) -> Result<(), Error> {
     if concat_file_path.exists() {
         fs::remove_file(concat_file_path)?;
     }
 
-    // todo look into not collecting so many times...
-    let lines = fs::read_dir(videos_dir)?
-        .collect::<Result<Vec<_>, _>>()?
+    let video_files = video_files
         .iter()
-        .map(fs::DirEntry::path)
         .map(fs::canonicalize)
-        .collect::<Result<Vec<_>, _>>()?
-        .iter()
-        .map(|path| format!("file {}", path.display()))
-        .rev()
-        .collect::<Vec<_>>()
-        .join("\n");
+        .collect::<Result<Vec<_>, _>>()?;
+    let lines = concat_lines(&video_files);
 
     let mut concat_file = fs::File::create(concat_file_path)?;
     write!(concat_file, "{lines}")?;
 
     let args = format!(
         "-y -f concat -safe 0 -i {} -c copy {}",
-        concat_file_path.display(),
-        output_file.display()
+        quote(concat_file_path),
+        quote(output_file)
     );
-    ffmpeg(args)?;
+    ffmpeg_with_progress(args, log_progress("Concatenating the chunks"))?;
     Ok(())
 }
 
+/// One `file` line per video for ffmpeg's concat demuxer
+/// Quoted so paths with spaces work, which means single quotes need to be escaped
+fn concat_lines(video_files: &[PathBuf]) -> String {
+    video_files
+        .iter()
+        .map(|path| {
+            let path = path.display().to_string().replace('\'', r"'\''");
+            format!("file '{path}'")
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 /// Extracts audio from `input_file` to the audio file you pass in
 pub fn extract_audio(input_file: &Path, audio_file: &Path) -> Result<(), Error> {
     let args = format!(
         "-y -i {} -map 0:a -c copy {}",
-        input_file.display(),
-        audio_file.display()
+        quote(input_file),
+        quote(audio_file)
     );
     ffmpeg(args)?;
     Ok(())
@@ -227,74 +652,309 @@ fn ffprobe<T: AsRef<str>>(requirements: T) -> Result<String, Error> {
     command::run(
         "ffprobe",
         requirements.as_ref().try_into()?,
-        Error::FfprobeCommand,
+        Program::Ffprobe,
     )
 }
 
-fn parse_timestamps(lines: &str) -> Result<Vec<Decimal>, Error> {
-    Regex::new(r"best_effort_timestamp_time=(\d+.\d+)|")
-        .unwrap()
-        .captures_iter(lines)
-        .filter_map(|caps| caps.get(1))
-        .map(|m| m.as_str())
-        .map(Decimal::from_str_exact)
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(Error::from)
+/// Asks ffprobe for the frame rate of the first video stream in `input_file`
+pub fn frame_rate(input_file: &Path) -> Result<FPS, Error> {
+    let args = format!(
+        "-v error -select_streams v:0 -show_entries stream=r_frame_rate -of default=noprint_wrappers=1:nokey=1 {}",
+        quote(input_file)
+    );
+    let stdout = ffprobe(args)?;
+    stdout.trim().parse()
+}
+
+/// Asks ffprobe for the number of frames in the first video stream of `input_file`
+/// Counting packets means reading the whole file, but nothing gets decoded
+pub fn frame_count(input_file: &Path) -> Result<NonZeroUsize, Error> {
+    let args = format!(
+        "-v error -select_streams v:0 -count_packets -show_entries stream=nb_read_packets -of default=noprint_wrappers=1:nokey=1 {}",
+        quote(input_file)
+    );
+    let stdout = ffprobe(args)?;
+    let stdout = stdout.trim();
+    stdout
+        .parse()
+        .map_err(|_| Error::UnexpectedOutput(stdout.to_owned()))
+}
+
+/// What ffprobe knows about a video we've created
+#[derive(Debug, PartialEq)]
+pub struct VideoProbe {
+    pub duration: Decimal,
+    pub frame_count: u64,
+}
+
+/// Decodes the whole video to count the frames, which also catches corrupt videos
+pub fn probe_video(video_file: &Path) -> Result<VideoProbe, Error> {
+    let args = format!(
+        "-v error -select_streams v:0 -count_frames -show_entries stream=nb_read_frames:format=duration -of default=noprint_wrappers=1 {}",
+        quote(video_file)
+    );
+    let stdout = ffprobe(args)?;
+    parse_video_probe(&stdout)
+}
+
+/// The value of `key` in ffprobe's `default=noprint_wrappers=1` output
+fn probe_value<'a>(lines: &'a str, key: &str) -> Result<&'a str, Error> {
+    lines
+        .lines()
+        .find_map(|line| line.trim().strip_prefix(key)?.strip_prefix('='))
+        .ok_or(Error::UnexpectedOutput(lines.to_owned()))
+}
+
+fn parse_video_probe(lines: &str) -> Result<VideoProbe, Error> {
+    let value = |key: &str| probe_value(lines, key);
+    let duration = Decimal::from_str_exact(value("duration")?)?;
+    let frame_count = value("nb_read_frames")?
+        .parse()
+        .map_err(|_| Error::UnexpectedOutput(lines.to_owned()))?;
+    Ok(VideoProbe {
+        duration,
+        frame_count,
+    })
 }
 
+/// Uncompressed frames that get piped between processes (see `FfmpegStepper::stream_chunk`)
+/// Every frame is the same number of bytes, so the size and pixel format are all a process
+/// needs to know to find where each frame starts
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFormat {
+    pub width: usize,
+    pub height: usize,
+    /// rgb24, or rgb48le for high bit depth sources (the same as the png files would use)
+    pub pix_fmt: &'static str,
+}
+
+impl RawFormat {
+    /// ffmpeg input options for reading these frames
+    pub fn input_args(&self) -> String {
+        format!(
+            "-f rawvideo -pix_fmt {} -s {}x{}",
+            self.pix_fmt, self.width, self.height
+        )
+    }
+
+    /// ffmpeg output options for writing these frames to stdout
+    pub fn output_args(&self) -> String {
+        format!("-f rawvideo -pix_fmt {} -", self.pix_fmt)
+    }
+
+    /// The size of one frame
+    pub fn frame_bytes(&self) -> u64 {
+        let bytes_per_pixel = if self.pix_fmt == "rgb48le" { 6 } else { 3 };
+        self.width as u64 * self.height as u64 * bytes_per_pixel
+    }
+}
+
+/// Asks ffprobe for the size + pixel format of the first video stream in `input_file`
+pub fn raw_format(input_file: &Path) -> Result<RawFormat, Error> {
+    let args = format!(
+        "-v error -select_streams v:0 -show_entries stream=width,height,pix_fmt -of default=noprint_wrappers=1 {}",
+        quote(input_file)
+    );
+    let stdout = ffprobe(args)?;
+    parse_raw_format(&stdout)
+}
+
+fn parse_raw_format(lines: &str) -> Result<RawFormat, Error> {
+    let dimension = |key: &str| {
+        probe_value(lines, key)?
+            .parse()
+            .map_err(|_| Error::UnexpectedOutput(lines.to_owned()))
+    };
+    let high_bit_depth = is_high_bit_depth(probe_value(lines, "pix_fmt")?);
+    Ok(RawFormat {
+        width: dimension("width")?,
+        height: dimension("height")?,
+        pix_fmt: if high_bit_depth { "rgb48le" } else { "rgb24" },
+    })
+}
+
+/// Whether `pix_fmt` has more than 8 bits per component (e.g. yuv420p10le or p010le)
+/// Those components take 2 bytes, so ffmpeg names them with their endianness. The numbers in
+/// the name can't be trusted, nv12 and yuv411p are 8 bit.
+fn is_high_bit_depth(pix_fmt: &str) -> bool {
+    let Some(pix_fmt) = pix_fmt
+        .strip_suffix("le")
+        .or_else(|| pix_fmt.strip_suffix("be"))
+    else {
+        return false;
+    };
+    // a whole pixel packed into 2 bytes, e.g. rgb565le
+    !["444", "555", "565"]
+        .iter()
+        .any(|packed| pix_fmt.ends_with(packed))
+}
+
+/// A frame's scene score in ffprobe's `-show_frames -of compact` output
+/// `None` if the line isn't a frame, or the frame has no time/score (like the first frame)
+fn parse_scene_score(line: &str, source_fps: &FPS) -> Option<Result<SceneScore, Error>> {
+    let field = |key: &str| {
+        line.split('|')
+            .find_map(|field| field.strip_prefix(key)?.strip_prefix('='))
+            .filter(|value| *value != "N/A")
+    };
+    let time = field("best_effort_timestamp_time")?;
+    let score = field("tag:lavfi.scene_score")?;
+    let parse = || {
+        Ok(SceneScore {
+            frame: source_fps.frame_at(&Decimal::from_str_exact(time)?)?,
+            score: Decimal::from_str_exact(score)?,
+        })
+    };
+    Some(parse())
+}
+
+/// A chapter's start in ffprobe's `-show_chapters -of compact` output
+fn parse_chapter_start(line: &str) -> Option<Result<Decimal, Error>> {
+    let start = line
+        .split('|')
+        .find_map(|field| field.strip_prefix("start_time="))?;
+    Some(Decimal::from_str_exact(start).map_err(|_| Error::UnexpectedOutput(line.to_owned())))
+}
+
+/// When each of the `input_file`'s chapters start, in seconds
+pub fn chapter_times(input_file: &Path) -> Result<Vec<Decimal>, Error> {
+    let args = format!(
+        "-v error -show_chapters -of compact=p=0 {}",
+        quote(input_file)
+    );
+    let times = ffprobe(args)?
+        .lines()
+        .filter_map(parse_chapter_start)
+        .collect::<Result<Vec<_>, _>>()?;
+    if times.is_empty() {
+        warn!("{input_file:?} doesn't have any chapters");
+    }
+    Ok(times)
+}
+
+/// The scene score of every frame in the `input_file`, or only the ones in the `window`
+/// Reading the whole video takes a while, so the scores are cached in the `scene_file`
 // https://superuser.com/questions/819573/split-up-a-video-using-ffmpeg-through-scene-detection
-pub fn find_scene_timestamps(
+pub fn find_scene_scores(
     input_file: &Path,
-    scene_gt: &str,
+    source_fps: &FPS,
+    window: Option<&Window>,
     scene_file: &Path,
-) -> Result<Vec<Decimal>, Error> {
+) -> Result<Vec<SceneScore>, Error> {
     if scene_file.exists() {
         debug!("{scene_file:?} exists, so using data in that file");
-        let decimals = fs::read_to_string(scene_file)?
-            .split('\n')
-            .map(|s| Decimal::from_str_exact(s).map_err(Error::from))
-            .collect::<Result<Vec<_>, Error>>()?;
-
-        return Ok(decimals);
+        return fs::read_to_string(scene_file)?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(SceneScore::from_cache_line)
+            .collect();
     }
     debug!("creating file: {scene_file:?}");
 
     let args = format!(
-        r#"-show_frames -of compact=p=0 -f lavfi "movie={},select=gt(scene\,{scene_gt})""#,
-        input_file.display()
+        r#"-show_frames -of compact=p=0 -f lavfi "{}""#,
+        scene_graph(input_file, window)
     );
 
-    let stdout = ffprobe(args)?;
-    let decimals = parse_timestamps(&stdout)?;
-    let mut f = fs::File::create(scene_file)?;
-    f.write_all(
-        decimals
-            .iter()
-            .map(|e| e.to_string())
-            .collect::<Vec<String>>()
-            .join("\n")
-            .as_str()
-            .as_bytes(),
+    // a long video has a lot of frames, so they're parsed as ffprobe finds them
+    let mut scores = vec![];
+    let mut parse_error = None;
+    command::run_lines(
+        "ffprobe",
+        args.as_str().try_into()?,
+        Program::Ffprobe,
+        |line| match parse_scene_score(line, source_fps) {
+            Some(Ok(score)) => scores.push(score),
+            Some(Err(e)) => {
+                parse_error.get_or_insert(e);
+            }
+            None => {}
+        },
+        |_| {},
     )?;
-    Ok(decimals)
+    if let Some(e) = parse_error {
+        return Err(e);
+    }
+    debug!("Found the scene scores of {} frames", scores.len());
+
+    // written in one go, so being interrupted can't leave half of the scores behind
+    let lines = scores
+        .iter()
+        .map(SceneScore::to_cache_line)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let temp_file = scene_file.with_extension("txt.tmp");
+    fs::write(&temp_file, lines)?;
+    fs::rename(&temp_file, scene_file)?;
+    Ok(scores)
 }
 
+/// The filter graph whose frames are the `input_file`'s (only the ones in the `window`) with
+/// their scene scores
+/// The movie filter seeks to the keyframe before the `window`, and keeps the frames'
+/// timestamps, so `trim` can stop at the window's end
+fn scene_graph(input_file: &Path, window: Option<&Window>) -> String {
+    let mut graph = format!("movie={}", input_file.display());
+    if let Some(window) = window {
+        if !window.start.is_zero() {
+            graph += &format!(":seek_point={}", window.start.normalize());
+        }
+        if let Some(end) = window.end {
+            graph += &format!(",trim=end={}", end.normalize());
+        }
+    }
+    // every frame passes, so ffprobe shows all of their scores
+    graph + r",select=gte(scene\,0)"
+}
+
+/// How far before a chunk `extract_args` seeks, so the keyframe ffmpeg lands on is before it
+/// even if the timestamps are a little off
+const SEEK_MARGIN_SECONDS: i64 = 1;
+
+/// Extracts exactly the frames in `time_range` from the `input_file`
+/// Seeking alone assumes a constant frame rate starting at 0, so we seek to a bit before the
+/// chunk (which is fast) and then only keep the frames whose timestamps (relative to the start
+/// of the input) round to the frames in the range (see `FPS::frame_at`). That way a variable
+/// frame rate or a non-zero start time can't shift the chunk, or duplicate/drop a frame at each
+/// boundary.
 pub fn extract_frames(
-    start: &Decimal,
+    time_range: &TimeRange,
+    source_fps: &FPS,
     input_file: &Path,
-    duration: &Decimal,
     output_dir: &Path,
 ) -> Result<(), Error> {
     let args = format!(
-        "-ss {start} -i {} -t {duration} -frame_pts true {}",
-        input_file.display(),
-        output_dir.join("frame_%08d.png").display()
+        "{} {}",
+        extract_args(time_range, source_fps, input_file),
+        quote(&output_dir.join("frame_%08d.png"))
     );
     ffmpeg(args)?;
     Ok(())
 }
 
-fn get_extension(path: &Path) -> Result<&str, Error> {
+/// The ffmpeg args for `extract_frames`, without the output
+/// `-copyts -start_at_zero` keeps the input's timestamps (minus its start time) for the
+/// `select` filter, which are then reset so the chunk's video starts at 0
+fn extract_args(time_range: &TimeRange, source_fps: &FPS, input_file: &Path) -> String {
+    let half_frame = source_fps.time_of(1) / Decimal::TWO;
+    let first = source_fps.time_of(time_range.start) - half_frame;
+    let last = source_fps.time_of(time_range.end()) - half_frame;
+    let seek = (first - Decimal::from(SEEK_MARGIN_SECONDS)).max(Decimal::ZERO);
+    let filter = format!(
+        r"select=gte(t\,{})*lt(t\,{}),setpts=PTS-STARTPTS",
+        first.round_dp(6).normalize(),
+        last.round_dp(6).normalize()
+    );
+    format!(
+        "-copyts -start_at_zero -ss {} -i {} -map 0:v:0 -vf {} -frames:v {} -fps_mode passthrough",
+        seek.round_dp(6).normalize(),
+        quote(input_file),
+        shell_words::quote(&filter),
+        time_range.frame_count()
+    )
+}
+
+pub fn get_extension(path: &Path) -> Result<&str, Error> {
     let extension = path
         .extension()
         .ok_or(Error::MissingExtension(path.to_path_buf()))?;
@@ -304,6 +964,85 @@ fn get_extension(path: &Path) -> Result<&str, Error> {
     Ok(extension)
 }
 
+/// `path` as an ffmpeg/ffprobe arg, quoted if it has spaces (or anything else the args would
+/// be split on, see `command::run`)
+pub fn quote(path: &Path) -> String {
+    shell_words::quote(&path.display().to_string()).into_owned()
+}
+
+/// The ffmpeg input args for the frames matching `glob` in `dir` (which ffmpeg runs in), in
+/// order of their names
+/// ffmpeg can't glob on Windows, so the frames are numbered there instead (see `number_frames`)
+pub fn frames_input(dir: &Path, glob: &str) -> Result<String, Error> {
+    if cfg!(windows) {
+        let pattern = number_frames(dir, glob)?;
+        return Ok(format!(
+            "-pattern_type sequence -start_number 1 -i {}",
+            shell_words::quote(&pattern)
+        ));
+    }
+    Ok(format!(
+        "-pattern_type glob -i {}",
+        shell_words::quote(glob)
+    ))
+}
+
+/// Renames the frames matching `glob` in `dir` (sorted by name, like a glob) to
+/// `seq_00000001.png`, `seq_00000002.png`... and returns their pattern
+/// The numbered frames keep their order, so it's fine to number them again (e.g. on a retry)
+fn number_frames(dir: &Path, glob: &str) -> Result<String, Error> {
+    let mut names = vec![];
+    for entry in fs::read_dir(dir).map_err(|_| Error::ReadDir(dir.to_path_buf()))? {
+        let name = entry?.file_name();
+        if let Some(name) = name.to_str().filter(|name| matches_glob(glob, name)) {
+            names.push(name.to_owned());
+        }
+    }
+    names.sort_unstable();
+    let extension = names
+        .first()
+        .and_then(|name| Path::new(name).extension()?.to_str())
+        .unwrap_or("png")
+        .to_owned();
+    for (index, name) in names.iter().enumerate() {
+        let numbered = format!("seq_{:08}.{extension}", index + 1);
+        fs::rename(dir.join(name), dir.join(numbered))?;
+    }
+    Ok(format!("seq_%08d.{extension}"))
+}
+
+/// Whether the file `name` matches `glob`, where `*` is any number of characters and `?` is
+/// any one character
+fn matches_glob(glob: &str, name: &str) -> bool {
+    let glob = glob.chars().collect::<Vec<_>>();
+    let name = name.chars().collect::<Vec<_>>();
+    let (mut g, mut n) = (0, 0);
+    // where the last `*` is in the glob + the name, to match one more character with it if the
+    // rest doesn't match
+    let mut star = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, n));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_g, star_n)) => {
+                    star = Some((star_g, star_n + 1));
+                    g = star_g + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
 fn dir_exists_or_create(path: &Path) -> Result<(), io::Error> {
     if !path.exists() {
         fs::create_dir_all(path)?;
@@ -314,6 +1053,9 @@ fn dir_exists_or_create(path: &Path) -> Result<(), io::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::TimeRanges;
+    use std::env;
+    use std::process;
 
     const SCENE_TIMESTAMPS: &str = "
 media_type=video|stream_index=0|key_frame=1|pkt_pts=9760|pkt_pts_time=9.760000|pkt_dts=9760|pkt_dts_time=9.760000|best_effort_timestamp=9760|best_effort_timestamp_time=9.760000|pkt_duration=N/A|pkt_duration_time=N/A|pkt_pos=858320|pkt_size=6220800|width=1920|height=1080|pix_fmt=yuv420p10le|sample_aspect_ratio=1:1|pict_type=I|coded_picture_number=0|display_picture_number=0|interlaced_frame=0|top_field_first=0|repeat_pict=0|color_range=unknown|color_space=unknown|color_primaries=unknown|color_transfer=unknown|chroma_location=unspecified|tag:lavfi.scene_score=0.504959
@@ -322,14 +1064,196 @@ media_type=video|stream_index=0|key_frame=1|pkt_pts=18936|pkt_pts_time=18.936000
 media_type=video|stream_index=0|key_frame=1|pkt_pts=22105|pkt_pts_time=22.105000|pkt_dts=22105|pkt_dts_time=22.105000|best_effort_timestamp=22105|best_effort_timestamp_time=22.105000|pkt_duration=N/A|pkt_duration_time=N/A|pkt_pos=2498438|pkt_size=6220800|width=1920|height=1080|pix_fmt=yuv420p10le|sample_aspect_ratio=1:1|pict_type=I|coded_picture_number=0|display_picture_number=0|interlaced_frame=0|top_field_first=0|repeat_pict=0|color_range=unknown|color_space=unknown|color_primaries=unknown|color_transfer=unknown|chroma_location=unspecified|tag:lavfi.scene_score=0.547889";
 
     #[test]
-    fn scene_time_stamps() {
-        let actual = parse_timestamps(SCENE_TIMESTAMPS).unwrap();
-        let expected = vec![
-            Decimal::from_str_exact("9.76").unwrap(),
-            Decimal::from_str_exact("13.513").unwrap(),
-            Decimal::from_str_exact("18.936").unwrap(),
-            Decimal::from_str_exact("22.105").unwrap(),
-        ];
+    fn concat_lines_keep_order() {
+        let video_files = (0..12)
+            .map(|i| PathBuf::from(format!("/tmp/videos/{i}.mkv")))
+            .collect::<Vec<_>>();
+        let actual = concat_lines(&video_files);
+        let lines = actual.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[2], "file '/tmp/videos/2.mkv'");
+        assert_eq!(lines[10], "file '/tmp/videos/10.mkv'");
+    }
+
+    #[test]
+    fn concat_lines_escape_quotes() {
+        let actual = concat_lines(&[PathBuf::from("/tmp/it's/0.mkv")]);
+        assert_eq!(actual, r"file '/tmp/it'\''s/0.mkv'");
+    }
+
+    #[test]
+    fn video_probe() {
+        let actual = parse_video_probe("nb_read_frames=417\nduration=6.950000\n").unwrap();
+        let expected = VideoProbe {
+            duration: Decimal::from_str_exact("6.95").unwrap(),
+            frame_count: 417,
+        };
+        assert_eq!(actual, expected);
+        assert!(parse_video_probe("nb_read_frames=N/A\nduration=6.950000\n").is_err());
+    }
+
+    #[test]
+    fn raw_formats() {
+        let actual = parse_raw_format("width=1920\nheight=1080\npix_fmt=yuv420p10le\n").unwrap();
+        let expected = RawFormat {
+            width: 1920,
+            height: 1080,
+            pix_fmt: "rgb48le",
+        };
+        assert_eq!(actual, expected);
+        assert_eq!(
+            actual.input_args(),
+            "-f rawvideo -pix_fmt rgb48le -s 1920x1080"
+        );
+        let actual = parse_raw_format("width=640\nheight=360\npix_fmt=yuv420p\n").unwrap();
+        assert_eq!(actual.pix_fmt, "rgb24");
+    }
+
+    #[test]
+    fn bit_depths() {
+        for pix_fmt in [
+            "yuv420p10le",
+            "yuv422p12be",
+            "p010le",
+            "gray16le",
+            "rgb48be",
+        ] {
+            assert!(is_high_bit_depth(pix_fmt), "{pix_fmt}");
+        }
+        for pix_fmt in ["yuv420p", "nv12", "yuv410p", "yuv411p", "rgb24", "rgb565le"] {
+            assert!(!is_high_bit_depth(pix_fmt), "{pix_fmt}");
+        }
+    }
+
+    #[test]
+    fn extract_by_timestamp() {
+        let source_fps = FPS::try_new(24, 1).unwrap();
+        let time_range = TimeRanges::try_new(240, 48, 288).unwrap().next().unwrap();
+        let actual = extract_args(&time_range, &source_fps, Path::new("in.mkv"));
+        assert_eq!(
+            actual,
+            r"-copyts -start_at_zero -ss 8.979167 -i in.mkv -map 0:v:0 -vf 'select=gte(t\,9.979167)*lt(t\,11.979167),setpts=PTS-STARTPTS' -frames:v 48 -fps_mode passthrough"
+        );
+        // can't seek before the start
+        let time_range = TimeRanges::try_new(0, 48, 48).unwrap().next().unwrap();
+        let actual = extract_args(&time_range, &source_fps, Path::new("in.mkv"));
+        assert!(actual.starts_with("-copyts -start_at_zero -ss 0 -i"));
+    }
+
+    #[test]
+    fn scene_scores() {
+        let source_fps = FPS::try_new(24, 1).unwrap();
+        let actual = SCENE_TIMESTAMPS
+            .lines()
+            .filter_map(|line| parse_scene_score(line, &source_fps))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let expected = [
+            (234, "0.504959"),
+            (324, "0.428674"),
+            (454, "0.990057"),
+            (531, "0.547889"),
+        ]
+        .map(|(frame, score)| SceneScore {
+            frame,
+            score: Decimal::from_str_exact(score).unwrap(),
+        });
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn globs() {
+        assert!(matches_glob("*.png", "00000001.png"));
+        assert!(!matches_glob("*.png", "00000001.jpg"));
+        assert!(matches_glob("frame_??.png", "frame_01.png"));
+        assert!(!matches_glob("frame_??.png", "frame_001.png"));
+        assert!(matches_glob("*_out*.png", "a_out_out_1.png"));
+        assert!(!matches_glob("*_out*.png", "a_in_1.png"));
+    }
+
+    #[test]
+    fn numbered_frames() {
+        let dir = env::temp_dir().join(format!("more-fps-numbered-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["b.png", "a.png", "c.png", "notes.txt"] {
+            fs::write(dir.join(name), name).unwrap();
+        }
+        assert_eq!(number_frames(&dir, "*.png").unwrap(), "seq_%08d.png");
+        // again, like on a retry
+        assert_eq!(number_frames(&dir, "*.png").unwrap(), "seq_%08d.png");
+        let first = fs::read_to_string(dir.join("seq_00000001.png"));
+        let last = fs::read_to_string(dir.join("seq_00000003.png"));
+        let notes = dir.join("notes.txt").exists();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(first.unwrap(), "a.png");
+        assert_eq!(last.unwrap(), "c.png");
+        assert!(notes);
+    }
+
+    #[test]
+    fn window_scene_graph() {
+        let input_file = Path::new("in.mkv");
+        assert_eq!(
+            scene_graph(input_file, None),
+            r"movie=in.mkv,select=gte(scene\,0)"
+        );
+        let window = Window {
+            start: Decimal::new(600, 1),
+            end: Some(Decimal::new(905, 1)),
+        };
+        assert_eq!(
+            scene_graph(input_file, Some(&window)),
+            r"movie=in.mkv:seek_point=60,trim=end=90.5,select=gte(scene\,0)"
+        );
+        let window = Window {
+            start: Decimal::ZERO,
+            end: None,
+        };
+        assert_eq!(
+            scene_graph(input_file, Some(&window)),
+            r"movie=in.mkv,select=gte(scene\,0)"
+        );
+    }
+
+    #[test]
+    fn clip_audio() {
+        let source_fps = FPS::try_new(24, 1).unwrap();
+        assert_eq!(
+            audio_args(None, &source_fps),
+            "-map 1 -c:a copy -c:s copy -map_chapters 1"
+        );
+        assert_eq!(
+            audio_args(Some(&[240..720, 2400..2412]), &source_fps),
+            r"-map 1:a? -af 'aselect=between(t\,10\,30)+between(t\,100\,100.5),asetpts=N/SR/TB' -map_chapters -1"
+        );
+    }
+
+    #[test]
+    fn chapter_starts() {
+        let lines = "id=0|time_base=1/1000|start=0|start_time=0.000000|end=83500|end_time=83.500000|tag:title=Intro
+id=1|time_base=1/1000|start=83500|start_time=83.500000|end=600000|end_time=600.000000|tag:title=Chase";
+        let starts = lines
+            .lines()
+            .filter_map(parse_chapter_start)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(starts, [Decimal::ZERO, Decimal::new(835, 1)]);
+    }
+
+    #[test]
+    fn ffmpeg_progress() {
+        let mut progress = FfmpegProgress::default();
+        let updates = "frame=240\nfps=48.00\nout_time_us=10010000\nspeed=1.92x\nprogress=continue\nframe=250\nout_time_us=N/A\nprogress=end"
+            .lines()
+            .filter(|line| progress.update(line))
+            .count();
+        assert_eq!(updates, 2);
+        let expected = FfmpegProgress {
+            frame: 250,
+            out_time: None,
+            speed: Some(String::from("1.92x")),
+            end: true,
+        };
+        assert_eq!(progress, expected);
+    }
 }
@@ -0,0 +1,176 @@
+use crate::command;
+use crate::command::PipedCommand;
+use crate::command::Program;
+use crate::ffmpeg;
+use crate::ffmpeg::RawFormat;
+use crate::interpolator::FrameCountSupport;
+use crate::Error;
+use crate::Interpolator;
+use std::fs;
+use std::num::NonZeroU64;
+use std::path::Path;
+
+/// Generates frames with one of ffmpeg's filters, so no ai binary or gpu is needed
+/// The quality isn't as good, but it's useful for testing the whole pipeline
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FfmpegInterpolator {
+    /// Motion interpolation (slow, decent quality)
+    /// https://ffmpeg.org/ffmpeg-filters.html#minterpolate
+    Minterpolate,
+    /// Blends neighbouring frames together (fast, blurry)
+    /// https://ffmpeg.org/ffmpeg-filters.html#framerate
+    Blend,
+    /// Repeats frames without generating any, used for fades (see `Treatment::Duplicate`)
+    /// https://ffmpeg.org/ffmpeg-filters.html#fps-1
+    Duplicate,
+}
+
+impl FfmpegInterpolator {
+    /// We pretend the `input_frames` are 1 second long, so asking for `frame_count` fps gives us
+    /// exactly `frame_count` frames (the last frame is cloned to fill the end of the second)
+    /// `input` is the input args for the frames (see `ffmpeg::frames_input`)
+    fn args(
+        &self,
+        input: &str,
+        input_frames: usize,
+        frame_count: NonZeroU64,
+        output_dir: &Path,
+    ) -> String {
+        format!(
+            "-y -framerate {input_frames} {input} {} {}",
+            self.filter_args(frame_count),
+            ffmpeg::quote(&output_dir.join("%08d.png"))
+        )
+    }
+
+    /// Same as `args`, but reading from stdin and writing to stdout
+    fn stream_args(
+        &self,
+        raw_format: &RawFormat,
+        input_frames: usize,
+        frame_count: NonZeroU64,
+    ) -> String {
+        format!(
+            "{} -framerate {input_frames} -i - {} {}",
+            raw_format.input_args(),
+            self.filter_args(frame_count),
+            raw_format.output_args()
+        )
+    }
+
+    fn filter_args(&self, frame_count: NonZeroU64) -> String {
+        let filter = match self {
+            Self::Minterpolate => format!("minterpolate=fps={frame_count}:mi_mode=mci"),
+            Self::Blend => format!("framerate=fps={frame_count}"),
+            Self::Duplicate => format!("fps=fps={frame_count}"),
+        };
+        format!("-vf {filter},tpad=stop=-1:stop_mode=clone -frames:v {frame_count}")
+    }
+}
+
+impl Interpolator for FfmpegInterpolator {
+    fn name(&self) -> String {
+        match self {
+            Self::Minterpolate => String::from("ffmpeg minterpolate"),
+            Self::Blend => String::from("ffmpeg framerate"),
+            Self::Duplicate => String::from("ffmpeg fps"),
+        }
+    }
+
+    fn frame_count_support(&self) -> FrameCountSupport {
+        FrameCountSupport::Target
+    }
+
+    fn interpolate(
+        &self,
+        input_dir: &Path,
+        output_dir: &Path,
+        frame_count: NonZeroU64,
+    ) -> Result<(), Error> {
+        let input_frames = fs::read_dir(input_dir)
+            .map_err(|_| Error::ReadDir(input_dir.to_path_buf()))?
+            .count();
+        let input = ffmpeg::frames_input(input_dir, "*.png")?;
+        let args = self.args(&input, input_frames, frame_count, output_dir);
+        let requirements = command::Requirements {
+            args: &args,
+            current_dir: input_dir.to_path_buf(),
+        };
+        command::run("ffmpeg", requirements, Program::Ffmpeg)?;
+        Ok(())
+    }
+
+    fn can_stream(&self) -> bool {
+        true
+    }
+
+    fn stream_command(
+        &self,
+        raw_format: &RawFormat,
+        input_frames: usize,
+        frame_count: NonZeroU64,
+    ) -> Option<PipedCommand> {
+        Some(PipedCommand {
+            binary: String::from("ffmpeg"),
+            args: self.stream_args(raw_format, input_frames, frame_count),
+            program: Program::Ffmpeg,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn minterpolate_args() {
+        let actual = FfmpegInterpolator::Minterpolate.args(
+            "-pattern_type glob -i '*.png'",
+            24,
+            NonZeroU64::new(60).unwrap(),
+            Path::new("/tmp/generated frames"),
+        );
+        assert_eq!(
+            actual,
+            "-y -framerate 24 -pattern_type glob -i '*.png' -vf minterpolate=fps=60:mi_mode=mci,tpad=stop=-1:stop_mode=clone -frames:v 60 '/tmp/generated frames/%08d.png'"
+        );
+    }
+
+    #[test]
+    fn blend_args() {
+        let actual = FfmpegInterpolator::Blend.args(
+            "-pattern_type glob -i '*.png'",
+            10,
+            NonZeroU64::new(20).unwrap(),
+            Path::new("/tmp/generated_frames"),
+        );
+        assert!(actual.contains("-vf framerate=fps=20,tpad"));
+        assert!(actual.ends_with("-frames:v 20 '/tmp/generated_frames/%08d.png'"));
+    }
+
+    #[test]
+    fn stream_args() {
+        let raw_format = RawFormat {
+            width: 1280,
+            height: 720,
+            pix_fmt: "rgb24",
+        };
+        let actual =
+            FfmpegInterpolator::Blend.stream_args(&raw_format, 12, NonZeroU64::new(30).unwrap());
+        assert_eq!(
+            actual,
+            "-f rawvideo -pix_fmt rgb24 -s 1280x720 -framerate 12 -i - -vf framerate=fps=30,tpad=stop=-1:stop_mode=clone -frames:v 30 -f rawvideo -pix_fmt rgb24 -"
+        );
+    }
+
+    #[test]
+    fn duplicate_args() {
+        let actual = FfmpegInterpolator::Duplicate.args(
+            "-pattern_type glob -i '*.png'",
+            10,
+            NonZeroU64::new(25).unwrap(),
+            Path::new("/tmp/generated_frames"),
+        );
+        assert!(actual.contains("-vf fps=fps=25,tpad"));
+    }
+}
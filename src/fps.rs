@@ -1,16 +1,252 @@
-use clap::ValueEnum;
-use std::num::NonZeroUsize;
+use crate::Error;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::num::NonZeroU64;
+use std::str::FromStr;
 
 /// How many frames do you want per second
-#[derive(ValueEnum, Copy, Clone, Debug, Default)]
-pub enum FPS {
-    /// 60 fps
-    #[default]
-    Sixty = 60,
+/// Either a whole number (`120`), a ratio (`60000/1001`) or a decimal (`59.94`)
+/// NTSC decimals like `23.976` and `59.94` become their exact `/1001` ratios
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
+pub struct FPS {
+    numerator: NonZeroU64,
+    denominator: NonZeroU64,
 }
 
 impl FPS {
-    pub fn non_zero_usize(self) -> NonZeroUsize {
-        NonZeroUsize::new(self as usize).unwrap()
+    /// Returns `None` if either part is zero
+    /// The ratio is reduced, so `120/2` and `60` are equal
+    pub fn try_new(numerator: u64, denominator: u64) -> Option<Self> {
+        let divisor = gcd(numerator, denominator);
+        Some(Self {
+            numerator: NonZeroU64::new(numerator.checked_div(divisor)?)?,
+            denominator: NonZeroU64::new(denominator.checked_div(divisor)?)?,
+        })
+    }
+
+    pub fn numerator(&self) -> NonZeroU64 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> NonZeroU64 {
+        self.denominator
+    }
+
+    /// `59.94` is really 60000/1001, so a decimal (with at least 2 places) that rounds
+    /// from a whole rate * 1000/1001 becomes that ratio, anything else is taken exactly
+    fn from_decimal(decimal: Decimal) -> Option<Self> {
+        let decimal = decimal.normalize();
+        if decimal.is_sign_negative() {
+            return None;
+        }
+        if decimal.scale() >= 2 {
+            let whole = (decimal * Decimal::from(1001) / Decimal::from(1000)).round();
+            let ntsc = Self::try_new(u64::try_from(whole).ok()? * 1000, 1001)?;
+            if ntsc
+                .frames_in(&Decimal::ONE)
+                .ok()?
+                .round_dp(decimal.scale())
+                == decimal
+            {
+                return Some(ntsc);
+            }
+        }
+        let denominator = 10u64.checked_pow(decimal.scale())?;
+        Self::try_new(u64::try_from(decimal.mantissa()).ok()?, denominator)
+    }
+
+    /// Multiplies the rate, e.g. 24000/1001 * 2 = 48000/1001
+    pub fn checked_mul(&self, multiplier: NonZeroU64) -> Option<Self> {
+        Self::try_new(
+            self.numerator.get().checked_mul(multiplier.get())?,
+            self.denominator.get(),
+        )
+    }
+
+    /// The number of frames that fit in `duration` seconds (not rounded)
+    /// Multiplying before dividing so we don't lose precision on rates like 60000/1001
+    pub fn frames_in(&self, duration: &Decimal) -> Result<Decimal, Error> {
+        let numerator = Decimal::from(self.numerator.get());
+        let denominator = Decimal::from(self.denominator.get());
+        duration
+            .checked_mul(numerator)
+            .and_then(|frames| frames.checked_div(denominator))
+            .ok_or(Error::MultiplicationOverflow(
+                self.to_string(),
+                duration.to_string(),
+            ))
+    }
+
+    /// The frame number being shown at `time` seconds (rounded to the closest frame)
+    pub fn frame_at(&self, time: &Decimal) -> Result<usize, Error> {
+        let frame = self.frames_in(time)?.round_dp(0);
+        usize::try_from(frame).map_err(|_| Error::InvalidFrameCount(frame))
+    }
+
+    /// When `frame` starts, in seconds
+    pub fn time_of(&self, frame: usize) -> Decimal {
+        Decimal::from(frame) * Decimal::from(self.denominator.get())
+            / Decimal::from(self.numerator.get())
+    }
+}
+
+impl Default for FPS {
+    fn default() -> Self {
+        Self::try_new(60, 1).unwrap()
+    }
+}
+
+/// Formatted the way ffmpeg's `-framerate` expects it
+impl fmt::Display for FPS {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.denominator.get() == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
+
+impl FromStr for FPS {
+    type Err = Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::InvalidFPS(s.to_owned());
+        let (numerator, denominator) = match s.trim().split_once('/') {
+            Some((numerator, denominator)) => (numerator.trim(), denominator.trim()),
+            None if s.contains('.') => {
+                let decimal = Decimal::from_str_exact(s.trim()).map_err(|_| invalid())?;
+                return Self::from_decimal(decimal).ok_or_else(invalid);
+            }
+            None => (s.trim(), "1"),
+        };
+        let numerator = numerator.parse().map_err(|_| invalid())?;
+        let denominator = denominator.parse().map_err(|_| invalid())?;
+        Self::try_new(numerator, denominator).ok_or_else(invalid)
+    }
+}
+
+/// What the ai binary should be aiming for
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Target {
+    /// An absolute frame rate regardless of the source
+    Fps(FPS),
+    /// Multiply the source's frame rate
+    /// Each pair of original frames gets `multiplier - 1` generated frames in between
+    Multiplier(NonZeroU64),
+}
+
+impl Target {
+    /// The frame rate of the videos we'll be creating
+    /// `source` is the input's frame rate (see `ffmpeg::frame_rate`)
+    pub fn output_fps(&self, source: FPS) -> Result<FPS, Error> {
+        match self {
+            Self::Fps(fps) => Ok(*fps),
+            Self::Multiplier(multiplier) => {
+                source
+                    .checked_mul(*multiplier)
+                    .ok_or(Error::MultiplicationOverflow(
+                        source.to_string(),
+                        multiplier.to_string(),
+                    ))
+            }
+        }
+    }
+}
+
+impl From<FPS> for String {
+    fn from(fps: FPS) -> Self {
+        fps.to_string()
+    }
+}
+
+impl TryFrom<String> for FPS {
+    type Error = Error;
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integer() {
+        let fps: FPS = "120".parse().unwrap();
+        assert_eq!(fps, FPS::try_new(120, 1).unwrap());
+        assert_eq!(fps.to_string(), "120");
+    }
+
+    #[test]
+    fn ratio() {
+        let fps: FPS = "60000/1001".parse().unwrap();
+        assert_eq!(fps.numerator().get(), 60000);
+        assert_eq!(fps.denominator().get(), 1001);
+        assert_eq!(fps.to_string(), "60000/1001");
+    }
+
+    #[test]
+    fn ratio_is_reduced() {
+        let fps: FPS = "120/2".parse().unwrap();
+        assert_eq!(fps, FPS::default());
+        assert_eq!(fps.to_string(), "60");
+    }
+
+    #[test]
+    fn decimal() {
+        let fps: FPS = "59.94".parse().unwrap();
+        assert_eq!(fps.to_string(), "60000/1001");
+        let fps: FPS = "23.976".parse().unwrap();
+        assert_eq!(fps.to_string(), "24000/1001");
+        let fps: FPS = "29.97".parse().unwrap();
+        assert_eq!(fps.to_string(), "30000/1001");
+        let fps: FPS = "12.5".parse().unwrap();
+        assert_eq!(fps.to_string(), "25/2");
+        let fps: FPS = "60.0".parse().unwrap();
+        assert_eq!(fps, FPS::default());
+        assert!("0.0".parse::<FPS>().is_err());
+        assert!("-29.97".parse::<FPS>().is_err());
+    }
+
+    #[test]
+    fn zero_is_invalid() {
+        assert!("0".parse::<FPS>().is_err());
+        assert!("60/0".parse::<FPS>().is_err());
+        assert!("sixty".parse::<FPS>().is_err());
+    }
+
+    #[test]
+    fn multiplied_ntsc() {
+        let fps: FPS = "24000/1001".parse().unwrap();
+        let doubled = fps.checked_mul(NonZeroU64::new(2).unwrap()).unwrap();
+        assert_eq!(doubled.to_string(), "48000/1001");
+    }
+
+    #[test]
+    fn frame_time_round_trip() {
+        let fps: FPS = "24000/1001".parse().unwrap();
+        let time = fps.time_of(12345);
+        assert_eq!(fps.frame_at(&time).unwrap(), 12345);
+        // a scene timestamp as ffprobe prints it
+        let time = Decimal::from_str_exact("13.513000").unwrap();
+        assert_eq!(fps.frame_at(&time).unwrap(), 324);
+    }
+
+    #[test]
+    fn frames_in_ntsc() {
+        let fps: FPS = "60000/1001".parse().unwrap();
+        let frames = fps.frames_in(&Decimal::from(1001)).unwrap();
+        assert_eq!(frames, Decimal::from(60000));
     }
 }
@@ -0,0 +1,299 @@
+use crate::Error;
+use crate::TimeRange;
+use crate::FPS;
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::ops::Range;
+
+/// Keeps track of how many frames we should have created vs how many we actually created
+/// Rounding each chunk on its own adds up over hundreds of chunks (and the audio drifts), so
+/// every target is based on where the whole video should be at the end of the chunk
+#[derive(Debug)]
+pub struct FrameAccountant {
+    /// frame rate of the video we're creating
+    fps: FPS,
+    /// frame rate of the input video
+    source_fps: FPS,
+    produced: u64,
+}
+
+impl FrameAccountant {
+    /// `start` is the source frame we're starting from (non-zero if we're continuing from a
+    /// previous run)
+    /// We assume everything before `start` was created perfectly
+    pub fn new(fps: FPS, source_fps: FPS, start: usize) -> Self {
+        let mut accountant = Self {
+            fps,
+            source_fps,
+            produced: 0,
+        };
+        accountant.produced = accountant.expected_at(start);
+        accountant
+    }
+
+    /// For creating `time_range` again (e.g. as smaller chunks) with exactly the `frames` it
+    /// had, so nothing after it drifts
+    pub fn replacing(fps: FPS, source_fps: FPS, time_range: &TimeRange, frames: u64) -> Self {
+        let mut accountant = Self::new(fps, source_fps, time_range.start);
+        accountant.produced = accountant
+            .expected_at(time_range.end())
+            .saturating_sub(frames);
+        accountant
+    }
+
+    /// The number of frames the output should have once we've reached the source's `frame`
+    /// Integer math, so there's nothing to round until the very end
+    pub fn expected_at(&self, frame: usize) -> u64 {
+        let numerator = frame as u128
+            * u128::from(self.fps.numerator().get())
+            * u128::from(self.source_fps.denominator().get());
+        let denominator = u128::from(self.fps.denominator().get())
+            * u128::from(self.source_fps.numerator().get());
+        // rounding half up
+        ((2 * numerator + denominator) / (2 * denominator)) as u64
+    }
+
+    /// The number of frames to create for `time_range`, corrected for the drift so far
+    /// Always at least 1 frame so every chunk has a video. If that overshoots, the next chunk
+    /// will make up for it
+    pub fn target(&self, time_range: &TimeRange) -> NonZeroU64 {
+        let expected = self.expected_at(time_range.end());
+        let target = expected.saturating_sub(self.produced).max(1);
+        NonZeroU64::new(target).unwrap()
+    }
+
+    /// Call this after the frames for a chunk were created
+    pub fn record(&mut self, produced: u64) {
+        self.produced += produced;
+    }
+
+    /// For source `frames` we don't create frames for at our rate (left out of the output or
+    /// kept at the source's rate), so everything after them is counted as if they were created
+    /// perfectly
+    pub fn skip(&mut self, frames: Range<usize>) {
+        self.produced += self.expected_at(frames.end) - self.expected_at(frames.start);
+    }
+
+    /// Call this when a chunk we `record`ed as `planned` frames ended up with `produced` frames
+    pub fn correct(&mut self, planned: u64, produced: u64) {
+        self.produced = self.produced - planned + produced;
+    }
+
+    pub fn produced(&self) -> u64 {
+        self.produced
+    }
+
+    /// Positive means we've created more frames than we should have by the source's `frame`
+    pub fn drift(&self, frame: usize) -> i128 {
+        i128::from(self.produced) - i128::from(self.expected_at(frame))
+    }
+}
+
+/// Hands out each chunk's frame count when a worker starts on it, so every chunk makes up for
+/// the frames the chunks before it actually ended up with
+/// The chunks finish in any order, so the ones before it that aren't done yet count as the
+/// frames they were asked for (and the chunks after them make up for the difference)
+#[derive(Debug)]
+pub struct FramePlanner {
+    accountant: FrameAccountant,
+    /// the chunks that aren't planned, in order: `None` is kept at the source's rate, otherwise
+    /// the frames a previous run created
+    kept: Vec<(TimeRange, Option<u64>)>,
+    /// the source frame the `accountant` is up to
+    accounted: usize,
+    /// chunks that are created again, each with its own accountant so the chunks planned
+    /// inside it add up to the frames it had (see `FrameAccountant::replacing`)
+    replaced: Vec<(TimeRange, FrameAccountant)>,
+    /// the frame counts handed out for chunks that aren't done yet, by chunk index (+ the
+    /// `replaced` chunk they're in)
+    in_flight: HashMap<usize, (Option<usize>, u64)>,
+}
+
+impl FramePlanner {
+    /// Everything before the first chunk (and in between the chunks) is counted as if it was
+    /// created perfectly
+    pub fn new(fps: FPS, source_fps: FPS, mut kept: Vec<(TimeRange, Option<u64>)>) -> Self {
+        kept.sort_unstable_by_key(|(time_range, _)| time_range.start);
+        Self {
+            accountant: FrameAccountant::new(fps, source_fps, 0),
+            kept,
+            accounted: 0,
+            replaced: vec![],
+            in_flight: HashMap::new(),
+        }
+    }
+
+    /// The chunks planned inside each of the `replaced` time ranges add up to its frame count
+    /// (a rerendered chunk's), instead of following on from the chunks before them
+    pub fn with_replaced(mut self, replaced: Vec<(TimeRange, u64)>) -> Self {
+        self.replaced = replaced
+            .into_iter()
+            .map(|(time_range, frames)| {
+                let accountant = FrameAccountant::replacing(
+                    self.accountant.fps,
+                    self.accountant.source_fps,
+                    &time_range,
+                    frames,
+                );
+                (time_range, accountant)
+            })
+            .collect();
+        self
+    }
+
+    /// The frame count for chunk `index` from `target` (e.g. `Interpolation::frame_count`)
+    /// Chunks have to be planned in the order they're in the video
+    pub fn plan(
+        &mut self,
+        index: usize,
+        time_range: &TimeRange,
+        target: impl FnOnce(&FrameAccountant) -> Result<NonZeroU64, Error>,
+    ) -> Result<NonZeroU64, Error> {
+        let replaced = self.replaced.iter().position(|(replaced, _)| {
+            replaced.start <= time_range.start && time_range.end() <= replaced.end()
+        });
+        if let Some(replaced) = replaced {
+            let accountant = &mut self.replaced[replaced].1;
+            let frame_count = target(accountant)?;
+            accountant.record(frame_count.get());
+            self.in_flight
+                .insert(index, (Some(replaced), frame_count.get()));
+            return Ok(frame_count);
+        }
+
+        let kept = self
+            .kept
+            .iter()
+            .take_while(|(kept, _)| kept.start < time_range.start)
+            .count();
+        for (kept, frames) in self.kept.drain(..kept) {
+            self.accountant.skip(self.accounted..kept.start);
+            match frames {
+                Some(frames) => self.accountant.record(frames),
+                None => self.accountant.skip(kept.start..kept.end()),
+            }
+            self.accounted = kept.end();
+        }
+        self.accountant.skip(self.accounted..time_range.start);
+        self.accounted = time_range.end();
+
+        let frame_count = target(&self.accountant)?;
+        self.accountant.record(frame_count.get());
+        self.in_flight.insert(index, (None, frame_count.get()));
+        Ok(frame_count)
+    }
+
+    /// Chunk `index` ended up with `frames`, which the chunks planned after this make up for
+    /// Chunks that weren't planned are ignored
+    pub fn done(&mut self, index: usize, frames: u64) {
+        let Some((replaced, planned)) = self.in_flight.remove(&index) else {
+            return;
+        };
+        let accountant = match replaced {
+            Some(replaced) => &mut self.replaced[replaced].1,
+            None => &mut self.accountant,
+        };
+        accountant.correct(planned, frames);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TimeRanges;
+
+    #[test]
+    fn no_drift_across_chunks() {
+        let fps: FPS = "60000/1001".parse().unwrap();
+        let source_fps: FPS = "24000/1001".parse().unwrap();
+        let end = 29599;
+        let mut accountant = FrameAccountant::new(fps, source_fps, 0);
+
+        // odd scene lengths so every chunk needs rounding
+        for time_range in TimeRanges::try_new(0, 7, end).unwrap() {
+            let target = accountant.target(&time_range);
+            accountant.record(target.get());
+        }
+        assert_eq!(accountant.drift(end), 0);
+        // 2.5 output frames per source frame
+        assert_eq!(accountant.produced(), 73998);
+    }
+
+    #[test]
+    fn corrects_for_previous_chunk() {
+        let fps = FPS::default();
+        let source_fps: FPS = "24".parse().unwrap();
+        let mut accountant = FrameAccountant::new(fps, source_fps, 0);
+        let mut time_ranges = TimeRanges::try_new(0, 24, 48).unwrap();
+
+        let first = time_ranges.next().unwrap();
+        assert_eq!(accountant.target(&first).get(), 60);
+        // the ai binary gave us 2 extra frames
+        accountant.record(62);
+
+        let second = time_ranges.next().unwrap();
+        assert_eq!(accountant.target(&second).get(), 58);
+    }
+
+    #[test]
+    fn resume_from_middle() {
+        let fps = FPS::default();
+        let source_fps: FPS = "24".parse().unwrap();
+        let accountant = FrameAccountant::new(fps, source_fps, 240);
+        assert_eq!(accountant.produced(), 600);
+    }
+
+    #[test]
+    fn skip_keeps_drift() {
+        let fps = FPS::default();
+        let source_fps: FPS = "24".parse().unwrap();
+        let mut accountant = FrameAccountant::new(fps, source_fps, 0);
+        accountant.record(62);
+        // a part of the input at its own rate
+        accountant.skip(24..48);
+        let third = TimeRanges::try_new(48, 24, 72).unwrap().next().unwrap();
+        assert_eq!(accountant.target(&third).get(), 58);
+    }
+
+    fn plan(planner: &mut FramePlanner, index: usize, time_range: &TimeRange) -> u64 {
+        planner
+            .plan(index, time_range, |accountant| {
+                Ok(accountant.target(time_range))
+            })
+            .unwrap()
+            .get()
+    }
+
+    #[test]
+    fn planner_corrects_for_finished_chunks() {
+        let fps = FPS::default();
+        let source_fps: FPS = "24".parse().unwrap();
+        let time_ranges = TimeRanges::try_new(0, 24, 120).unwrap().collect::<Vec<_>>();
+        // the second chunk was done by a previous run, with 1 frame too few
+        let mut planner = FramePlanner::new(fps, source_fps, vec![(time_ranges[1], Some(59))]);
+
+        assert_eq!(plan(&mut planner, 0, &time_ranges[0]), 60);
+        assert_eq!(plan(&mut planner, 2, &time_ranges[2]), 61);
+        // the first chunk finished with 2 extra frames while the third is still going
+        planner.done(0, 62);
+        assert_eq!(plan(&mut planner, 3, &time_ranges[3]), 58);
+        planner.done(2, 61);
+        planner.done(3, 58);
+        assert_eq!(plan(&mut planner, 4, &time_ranges[4]), 60);
+    }
+
+    #[test]
+    fn replaced_chunk_keeps_its_frames() {
+        let fps = FPS::default();
+        let source_fps: FPS = "24".parse().unwrap();
+        let time_ranges = TimeRanges::try_new(0, 24, 96).unwrap().collect::<Vec<_>>();
+        let chunk = TimeRanges::try_new(48, 48, 96).unwrap().next().unwrap();
+        // the chunk had 1 frame too many, which the chunks after it made up for
+        let mut planner =
+            FramePlanner::new(fps, source_fps, vec![]).with_replaced(vec![(chunk, 121)]);
+
+        assert_eq!(plan(&mut planner, 10, &time_ranges[2]), 61);
+        planner.done(10, 62);
+        assert_eq!(plan(&mut planner, 11, &time_ranges[3]), 59);
+    }
+}
@@ -1,65 +1,224 @@
 use crate::command;
+use crate::command::PipedCommand;
+use crate::command::Program;
+use crate::ffmpeg::RawFormat;
+use crate::interpolator::Backend;
+use crate::interpolator::FrameCountSupport;
 use crate::Error;
-use crate::NonZeroDecimal;
-use crate::FPS;
-use rust_decimal::Decimal;
-use std::fs;
+use crate::Interpolator;
+use log::debug;
+use std::num::NonZeroU64;
 use std::path::Path;
-use std::path::PathBuf;
 
+/// Generates frames with an ai binary (rife-ncnn-vulkan by default)
 #[derive(Debug)]
 pub struct FrameGenerator<'a> {
     pub binary: &'a Path,
     pub model: &'a Path,
-    pub fps: FPS,
-    pub input_dir: &'a Path,
-    pub output_dir: &'a PathBuf,
+    pub backend: Backend,
+    /// Only used by `Backend::Custom`, see `Backend::template`
+    pub template: Option<&'a str>,
+    /// Only used by `Backend::Custom`, for binaries that can read/write raw frames over
+    /// stdin/stdout
+    pub stream_template: Option<&'a str>,
+    /// Only used by `Backend::Custom`, for binaries that can't target a frame count
+    pub fixed_multiplier: Option<NonZeroU64>,
+    pub output_glob: &'a str,
     pub extra_args: &'a str,
 }
 
 impl<'a> FrameGenerator<'a> {
-    pub fn clear_output_dir(&self) -> Result<(), Error> {
-        if self.output_dir.exists() {
-            fs::remove_dir_all(self.output_dir)?;
-            fs::create_dir_all(self.output_dir)?;
+    fn template(&self) -> Result<&str, Error> {
+        self.backend
+            .template()
+            .or(self.template)
+            .ok_or(Error::MissingTemplate(self.backend))
+    }
+
+    /// Fills in the backend's template
+    /// Paths are quoted because the args are split like a shell would
+    fn args(
+        &self,
+        input_dir: &Path,
+        output_dir: &Path,
+        frame_count: NonZeroU64,
+    ) -> Result<String, Error> {
+        let quote = |path: &Path| shell_words::quote(&path.display().to_string()).into_owned();
+        let args = self
+            .template()?
+            .replace("{model}", &quote(self.model))
+            .replace("{input}", &quote(input_dir))
+            .replace("{output}", &quote(output_dir))
+            .replace("{count}", &frame_count.to_string());
+        Ok(format!("{args} {}", self.extra_args))
+    }
+
+    /// Fills in the `stream_template`
+    fn stream_args(
+        &self,
+        raw_format: &RawFormat,
+        input_frames: usize,
+        frame_count: NonZeroU64,
+    ) -> Option<String> {
+        let args = self
+            .stream_template?
+            .replace(
+                "{model}",
+                &shell_words::quote(&self.model.display().to_string()),
+            )
+            .replace("{width}", &raw_format.width.to_string())
+            .replace("{height}", &raw_format.height.to_string())
+            .replace("{pix_fmt}", raw_format.pix_fmt)
+            .replace("{input_count}", &input_frames.to_string())
+            .replace("{count}", &frame_count.to_string());
+        Some(format!("{args} {}", self.extra_args))
+    }
+}
+
+impl<'a> Interpolator for FrameGenerator<'a> {
+    fn name(&self) -> String {
+        format!("{} ({})", self.backend, self.binary.display())
+    }
+
+    fn frame_count_support(&self) -> FrameCountSupport {
+        match (self.backend, self.fixed_multiplier) {
+            (Backend::Cain, _) => FrameCountSupport::FixedMultiplier(NonZeroU64::new(2).unwrap()),
+            (Backend::Custom, Some(multiplier)) => FrameCountSupport::FixedMultiplier(multiplier),
+            _ => FrameCountSupport::Target,
         }
-        Ok(())
     }
-    pub fn execute(&self, duration: NonZeroDecimal) -> Result<&Path, Error> {
-        self.clear_output_dir()?;
-
-        let frame_count = self.frame_count(duration)?;
-        let args = format!(
-            "-m {} -i {} -o {} -n {frame_count} {}",
-            self.model.display(),
-            self.input_dir.display(),
-            self.output_dir.display(),
-            self.extra_args
-        );
-        command::run(
-            &self.binary.display().to_string(),
+
+    fn output_glob(&self) -> &str {
+        self.output_glob
+    }
+
+    fn interpolate(
+        &self,
+        input_dir: &Path,
+        output_dir: &Path,
+        frame_count: NonZeroU64,
+    ) -> Result<(), Error> {
+        let args = self.args(input_dir, output_dir, frame_count)?;
+        // most ai binaries print a line per generated frame, which is shown as it happens with
+        // RUST_LOG=debug
+        let binary = self.binary.display().to_string();
+        command::run_lines(
+            &binary,
             args.as_str().try_into()?,
-            Error::AICommand,
-        )?;
-        Ok(self.output_dir)
+            Program::AI,
+            |line| debug!("{binary}: {line}"),
+            |line| debug!("{binary}: {line}"),
+        )
+    }
+
+    fn can_stream(&self) -> bool {
+        self.backend == Backend::Custom && self.stream_template.is_some()
     }
 
-    /// the frame count for ai binary to target
-    /// denoted as the -n flag
-    /// While this function does always return a `NonZeroDecimal`, we need a `Decimal` to `Display` in
-    /// `execute`. So there's no point in returning a `NonZeroDecimal`
-    fn frame_count(&self, duration: NonZeroDecimal) -> Result<Decimal, Error> {
-        let fps = *NonZeroDecimal::try_new(self.fps.non_zero_usize().get())
-            .ok_or(Error::BadFPS(self.fps.non_zero_usize()))?;
-
-        let frame_count = fps
-            .checked_mul(*duration)
-            .ok_or(Error::MultiplicationOverflow(
-                fps.to_string(),
-                duration.to_string(),
-            ))?
-            .round_dp(0);
-
-        Ok(frame_count)
+    fn stream_command(
+        &self,
+        raw_format: &RawFormat,
+        input_frames: usize,
+        frame_count: NonZeroU64,
+    ) -> Option<PipedCommand> {
+        if !self.can_stream() {
+            return None;
+        }
+        Some(PipedCommand {
+            binary: self.binary.display().to_string(),
+            args: self.stream_args(raw_format, input_frames, frame_count)?,
+            program: Program::AI,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame_generator(backend: Backend, template: Option<&str>) -> FrameGenerator<'_> {
+        FrameGenerator {
+            binary: Path::new("rife-ncnn-vulkan"),
+            model: Path::new("models/rife v4.6"),
+            backend,
+            template,
+            stream_template: None,
+            fixed_multiplier: None,
+            output_glob: "*.png",
+            extra_args: "-g 0",
+        }
+    }
+
+    #[test]
+    fn rife_args() {
+        let frame_generator = frame_generator(Backend::Rife, None);
+        let actual = frame_generator
+            .args(
+                Path::new("/tmp/frames"),
+                Path::new("/tmp/generated_frames"),
+                NonZeroU64::new(150).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(
+            actual,
+            "-m 'models/rife v4.6' -i /tmp/frames -o /tmp/generated_frames -n 150 -g 0"
+        );
+    }
+
+    #[test]
+    fn custom_args() {
+        let frame_generator = frame_generator(
+            Backend::Custom,
+            Some("--in {input} --out {output} -x {count}"),
+        );
+        let actual = frame_generator
+            .args(
+                Path::new("/tmp/frames"),
+                Path::new("/tmp/out"),
+                NonZeroU64::new(3).unwrap(),
+            )
+            .unwrap();
+        assert_eq!(actual, "--in /tmp/frames --out /tmp/out -x 3 -g 0");
+        assert_eq!(
+            frame_generator.frame_count_support(),
+            FrameCountSupport::Target
+        );
+    }
+
+    #[test]
+    fn custom_needs_template() {
+        let frame_generator = frame_generator(Backend::Custom, None);
+        assert!(frame_generator.template().is_err());
+    }
+
+    #[test]
+    fn custom_stream_args() {
+        let mut frame_generator = frame_generator(Backend::Rife, None);
+        frame_generator.stream_template =
+            Some("-m {model} -s {width}x{height} -f {pix_fmt} -c {input_count}:{count}");
+        assert!(!frame_generator.can_stream());
+
+        frame_generator.backend = Backend::Custom;
+        let raw_format = RawFormat {
+            width: 1920,
+            height: 1080,
+            pix_fmt: "rgb24",
+        };
+        let actual = frame_generator
+            .stream_command(&raw_format, 24, NonZeroU64::new(60).unwrap())
+            .unwrap();
+        assert_eq!(
+            actual.args,
+            "-m 'models/rife v4.6' -s 1920x1080 -f rgb24 -c 24:60 -g 0"
+        );
+    }
+
+    #[test]
+    fn cain_is_2x() {
+        let frame_generator = frame_generator(Backend::Cain, None);
+        assert_eq!(
+            frame_generator.frame_count_support(),
+            FrameCountSupport::FixedMultiplier(NonZeroU64::new(2).unwrap())
+        );
     }
 }
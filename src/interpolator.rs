@@ -0,0 +1,458 @@
+use crate::command::PipedCommand;
+use crate::ffmpeg::RawFormat;
+use crate::Error;
+use crate::FrameAccountant;
+use crate::Target;
+use crate::TimeRange;
+use clap::ValueEnum;
+use log::warn;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::num::NonZeroU64;
+use std::path::Path;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use strum_macros::Display;
+
+/// Something that can generate intermediate frames from a directory of frames
+/// `Sync` so several workers can use interpolators at once (see `Pipeline`)
+pub trait Interpolator: fmt::Debug + Sync {
+    /// Used in logs/errors
+    fn name(&self) -> String;
+
+    /// Whether we can ask for an exact number of frames
+    fn frame_count_support(&self) -> FrameCountSupport;
+
+    /// Glob (relative to the output dir) matching the frames `interpolate` creates
+    fn output_glob(&self) -> &str {
+        "*.png"
+    }
+
+    /// Generates frames from the frames in `input_dir` into the (empty) `output_dir`
+    /// `frame_count` is ignored by backends that only support a fixed multiplier
+    fn interpolate(
+        &self,
+        input_dir: &Path,
+        output_dir: &Path,
+        frame_count: NonZeroU64,
+    ) -> Result<(), Error>;
+
+    /// Whether `stream_command` can be used instead of `interpolate`
+    fn can_stream(&self) -> bool {
+        false
+    }
+
+    /// A command that reads `input_frames` frames in the `raw_format` from stdin, and writes
+    /// `frame_count` frames in the same format to stdout
+    /// `None` if the backend only works with files
+    fn stream_command(
+        &self,
+        _raw_format: &RawFormat,
+        _input_frames: usize,
+        _frame_count: NonZeroU64,
+    ) -> Option<PipedCommand> {
+        None
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FrameCountSupport {
+    /// Can create any number of frames (like rife's `-n` flag)
+    Target,
+    /// Always multiplies the number of frames by this much
+    FixedMultiplier(NonZeroU64),
+}
+
+/// AI binaries we know how to call (+ ffmpeg for when there's no ai binary)
+#[derive(
+    ValueEnum, Copy, Clone, Debug, Default, Display, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum Backend {
+    /// rife-ncnn-vulkan
+    #[default]
+    Rife,
+    /// ifrnet-ncnn-vulkan
+    Ifrnet,
+    /// dain-ncnn-vulkan
+    Dain,
+    /// cain-ncnn-vulkan (only supports 2x)
+    Cain,
+    /// Any other binary, using the --ai-template option
+    Custom,
+    /// No ai binary needed: ffmpeg's minterpolate filter (slow, decent quality)
+    Minterpolate,
+    /// No ai binary needed: ffmpeg's framerate filter which blends frames (fast, blurry)
+    Blend,
+}
+
+impl Backend {
+    /// The arguments passed to the ai binary
+    /// {model}, {input}, {output} and {count} get replaced (see `FrameGenerator`)
+    /// `None` for `Custom` because the user needs to provide it
+    pub fn template(&self) -> Option<&'static str> {
+        match self {
+            Self::Rife | Self::Ifrnet | Self::Dain => {
+                Some("-m {model} -i {input} -o {output} -n {count}")
+            }
+            Self::Cain => Some("-m {model} -i {input} -o {output}"),
+            Self::Custom | Self::Minterpolate | Self::Blend => None,
+        }
+    }
+}
+
+/// Ties an `Interpolator` to the directories + `Target` of this run
+/// Every chunk gets its own sub directory (named after the chunk's index) so several chunks can
+/// be worked on at once
+#[derive(Debug)]
+pub struct Interpolation<'a> {
+    pub interpolator: &'a dyn Interpolator,
+    pub target: Target,
+    pub input_dir: &'a Path,
+    pub output_dir: &'a PathBuf,
+    pub retry_policy: RetryPolicy,
+    /// Tried once more after the retries run out (e.g. with different --ai-args)
+    pub fallback: Option<&'a dyn Interpolator>,
+}
+
+/// How often a chunk is tried again when the interpolator fails
+/// GPUs can fail on one chunk (e.g. a lost vulkan device) and be fine on the next try
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub retries: usize,
+    /// How long to wait before the first retry, doubled after every retry
+    pub backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            retries: 0,
+            backoff: Duration::ZERO,
+        }
+    }
+}
+
+impl<'a> Interpolation<'a> {
+    /// Errors if the interpolator can't produce the frame rate we're targeting
+    pub fn try_new(
+        interpolator: &'a dyn Interpolator,
+        target: Target,
+        input_dir: &'a Path,
+        output_dir: &'a PathBuf,
+    ) -> Result<Self, Error> {
+        match (interpolator.frame_count_support(), target) {
+            (FrameCountSupport::Target, _) => (),
+            (FrameCountSupport::FixedMultiplier(fixed), Target::Multiplier(multiplier))
+                if fixed == multiplier => {}
+            (FrameCountSupport::FixedMultiplier(fixed), _) => {
+                return Err(Error::UnsupportedTarget(
+                    interpolator.name(),
+                    format!("only supports --multiplier {fixed}"),
+                ))
+            }
+        };
+        Ok(Self {
+            interpolator,
+            target,
+            input_dir,
+            output_dir,
+            retry_policy: RetryPolicy::default(),
+            fallback: None,
+        })
+    }
+
+    /// The same, but generating frames with `interpolator` instead (without retries/fallback)
+    /// `interpolator` should support the same frame counts
+    pub fn using(&self, interpolator: &'a dyn Interpolator) -> Self {
+        Self {
+            interpolator,
+            target: self.target,
+            input_dir: self.input_dir,
+            output_dir: self.output_dir,
+            retry_policy: RetryPolicy::default(),
+            fallback: None,
+        }
+    }
+
+    pub fn with_retries(
+        mut self,
+        retry_policy: RetryPolicy,
+        fallback: Option<&'a dyn Interpolator>,
+    ) -> Self {
+        self.retry_policy = retry_policy;
+        self.fallback = fallback;
+        self
+    }
+
+    /// Calls `attempt` with the interpolator until it succeeds, following the `retry_policy`
+    /// and then trying the `fallback`
+    /// Only the ai binary failing (`Error::AICommand`) is retried
+    pub fn with_retry<T>(
+        &self,
+        index: usize,
+        attempt: impl Fn(&dyn Interpolator) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let mut backoff = self.retry_policy.backoff;
+        let mut retries = 0;
+        loop {
+            let error = match attempt(self.interpolator) {
+                Ok(output) => return Ok(output),
+                Err(e @ Error::AICommand(_)) => e,
+                // not the ai binary's fault (e.g. ffmpeg failed, the disk is full or we were
+                // interrupted), so trying again won't help
+                Err(e) => return Err(e),
+            };
+            if retries < self.retry_policy.retries {
+                retries += 1;
+                warn!(
+                    "{} failed on chunk {index}, retrying in {backoff:?} ({retries}/{}): {error}",
+                    self.interpolator.name(),
+                    self.retry_policy.retries
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+                continue;
+            }
+            let Some(fallback) = self.fallback else {
+                return Err(error);
+            };
+            warn!(
+                "{} failed on chunk {index}, trying the fallback: {error}",
+                self.interpolator.name()
+            );
+            return attempt(fallback);
+        }
+    }
+
+    /// Where chunk `index`'s extracted frames are
+    pub fn chunk_input_dir(&self, index: usize) -> PathBuf {
+        self.input_dir.join(index.to_string())
+    }
+
+    /// Where chunk `index`'s generated frames go
+    pub fn chunk_output_dir(&self, index: usize) -> PathBuf {
+        self.output_dir.join(index.to_string())
+    }
+
+    pub fn clear_output_dir(&self) -> Result<(), Error> {
+        if self.output_dir.exists() {
+            fs::remove_dir_all(self.output_dir)?;
+            fs::create_dir_all(self.output_dir)?;
+        }
+        Ok(())
+    }
+
+    /// Has the interpolator generate `frame_count` frames from chunk `index`'s extracted frames
+    /// Failures are retried (see `with_retry`)
+    pub fn execute(&self, index: usize, frame_count: NonZeroU64) -> Result<PathBuf, Error> {
+        let output_dir = self.chunk_output_dir(index);
+        self.with_retry(index, |interpolator| {
+            // a failed attempt can leave some frames behind
+            if output_dir.exists() {
+                fs::remove_dir_all(&output_dir)?;
+            }
+            fs::create_dir_all(&output_dir)?;
+            interpolator.interpolate(&self.chunk_input_dir(index), &output_dir, frame_count)
+        })?;
+        Ok(output_dir)
+    }
+
+    /// The number of frames the interpolator should create from chunk `index`'s extracted frames
+    /// With a multiplier, it's based on what was actually extracted (not the `time_range`), so
+    /// the original frames line up even if a frame was dropped/duplicated at the chunk's edges
+    pub fn frame_count(
+        &self,
+        index: usize,
+        time_range: &TimeRange,
+        accountant: &FrameAccountant,
+    ) -> Result<NonZeroU64, Error> {
+        match self.target {
+            Target::Fps(_) => Ok(accountant.target(time_range)),
+            Target::Multiplier(multiplier) => {
+                let input_frames = count_files(&self.chunk_input_dir(index))?;
+                u64::try_from(input_frames)
+                    .ok()
+                    .and_then(|input_frames| input_frames.checked_mul(multiplier.get()))
+                    .and_then(NonZeroU64::new)
+                    .ok_or(Error::MultiplicationOverflow(
+                        input_frames.to_string(),
+                        multiplier.to_string(),
+                    ))
+            }
+        }
+    }
+
+    /// Same as `frame_count`, but without needing the frames to be extracted first (for
+    /// planning ahead, and for streamed chunks)
+    pub fn expected_frame_count(
+        &self,
+        time_range: &TimeRange,
+        accountant: &FrameAccountant,
+    ) -> NonZeroU64 {
+        match self.target {
+            Target::Fps(_) => accountant.target(time_range),
+            Target::Multiplier(multiplier) => {
+                NonZeroU64::new(time_range.frame_count().get() as u64)
+                    .and_then(|input_frames| input_frames.checked_mul(multiplier))
+                    .unwrap_or(NonZeroU64::MAX)
+            }
+        }
+    }
+
+    /// Number of frames the interpolator generated for chunk `index`
+    pub fn output_frame_count(&self, index: usize) -> Result<u64, Error> {
+        Ok(count_files(&self.chunk_output_dir(index))? as u64)
+    }
+}
+
+fn count_files(dir: &Path) -> Result<usize, Error> {
+    let count = fs::read_dir(dir)
+        .map_err(|_| Error::ReadDir(dir.to_path_buf()))?
+        .count();
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::CommandFailure;
+    use crate::TimeRanges;
+    use crate::FPS;
+    use std::env;
+    use std::io;
+    use std::process;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering;
+
+    /// Fails the first `failures` times it's asked to interpolate, like the ai binary running
+    /// out of memory (or the disk filling up if it's `full`)
+    #[derive(Debug)]
+    struct Flaky {
+        failures: usize,
+        full: bool,
+        attempts: AtomicUsize,
+    }
+
+    impl Flaky {
+        fn new(failures: usize) -> Self {
+            Self {
+                failures,
+                full: false,
+                attempts: AtomicUsize::new(0),
+            }
+        }
+
+        fn full(failures: usize) -> Self {
+            Self {
+                full: true,
+                ..Self::new(failures)
+            }
+        }
+
+        fn attempts(&self) -> usize {
+            self.attempts.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Interpolator for Flaky {
+        fn name(&self) -> String {
+            String::from("flaky")
+        }
+
+        fn frame_count_support(&self) -> FrameCountSupport {
+            FrameCountSupport::Target
+        }
+
+        fn interpolate(&self, _: &Path, _: &Path, _: NonZeroU64) -> Result<(), Error> {
+            match self.attempts.fetch_add(1, Ordering::Relaxed) < self.failures {
+                true if self.full => Err(Error::Io(io::Error::from(io::ErrorKind::StorageFull))),
+                true => Err(Error::AICommand(CommandFailure {
+                    command: String::from("rife-ncnn-vulkan"),
+                    status: String::from("exit status: 1"),
+                    stderr_tail: String::from("out of memory"),
+                })),
+                false => Ok(()),
+            }
+        }
+    }
+
+    /// Interpolates chunk 0 with `primary`, retrying twice before trying the `fallback`
+    fn execute(primary: &Flaky, fallback: &Flaky, name: &str) -> Result<PathBuf, Error> {
+        let output_dir = env::temp_dir().join(format!("more-fps-{name}-{}", process::id()));
+        let target = Target::Multiplier(NonZeroU64::new(2).unwrap());
+        let interpolation = Interpolation::try_new(primary, target, Path::new("in"), &output_dir)
+            .unwrap()
+            .with_retries(
+                RetryPolicy {
+                    retries: 2,
+                    backoff: Duration::ZERO,
+                },
+                Some(fallback),
+            );
+        let result = interpolation.execute(0, NonZeroU64::new(48).unwrap());
+        fs::remove_dir_all(&output_dir).unwrap();
+        result
+    }
+
+    #[test]
+    fn retries() {
+        let primary = Flaky::new(2);
+        let fallback = Flaky::new(0);
+        assert!(execute(&primary, &fallback, "retries").is_ok());
+        assert_eq!(primary.attempts(), 3);
+        assert_eq!(fallback.attempts(), 0);
+    }
+
+    #[test]
+    fn falls_back() {
+        let primary = Flaky::new(usize::MAX);
+        let fallback = Flaky::new(0);
+        assert!(execute(&primary, &fallback, "falls-back").is_ok());
+        assert_eq!(primary.attempts(), 3);
+        assert_eq!(fallback.attempts(), 1);
+
+        let primary = Flaky::new(usize::MAX);
+        let fallback = Flaky::new(usize::MAX);
+        assert!(execute(&primary, &fallback, "gives-up").is_err());
+        assert_eq!(primary.attempts(), 3);
+        assert_eq!(fallback.attempts(), 1);
+    }
+
+    #[test]
+    fn only_retries_the_ai() {
+        let primary = Flaky::full(1);
+        let fallback = Flaky::new(0);
+        assert!(execute(&primary, &fallback, "only-ai").is_err());
+        assert_eq!(primary.attempts(), 1);
+        assert_eq!(fallback.attempts(), 0);
+    }
+
+    #[test]
+    fn counts_extracted_frames() {
+        let dir = env::temp_dir().join(format!("more-fps-extracted-{}", process::id()));
+        let input_dir = dir.join("frames");
+        let output_dir = dir.join("generated");
+        let primary = Flaky::new(0);
+        let target = Target::Multiplier(NonZeroU64::new(2).unwrap());
+        let interpolation =
+            Interpolation::try_new(&primary, target, &input_dir, &output_dir).unwrap();
+        // the select at the chunk's edge dropped a frame
+        fs::create_dir_all(interpolation.chunk_input_dir(3)).unwrap();
+        for frame in 1..=47 {
+            let frame_file = format!("frame_{frame:08}.png");
+            fs::write(interpolation.chunk_input_dir(3).join(frame_file), []).unwrap();
+        }
+        let time_range = TimeRanges::try_new(240, 48, 288).unwrap().next().unwrap();
+        let accountant = FrameAccountant::new(FPS::default(), FPS::default(), 0);
+        let frame_count = interpolation.frame_count(3, &time_range, &accountant);
+        let expected = interpolation.expected_frame_count(&time_range, &accountant);
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(frame_count.unwrap().get(), 94);
+        assert_eq!(expected.get(), 96);
+    }
+}
@@ -1,27 +1,62 @@
 mod cli;
 pub use cli::Cli;
+pub use cli::Command;
+pub use cli::Rerender;
 
 pub mod command;
 
+pub mod cuts;
+
+pub mod encoder;
+pub use encoder::Encoder;
+
+mod disk_budget;
+pub use disk_budget::DiskBudget;
+
 mod error;
 pub use error::Error;
 
 pub mod ffmpeg;
 pub use ffmpeg::FfmpegStepper;
 
+mod ffmpeg_interpolator;
+pub use ffmpeg_interpolator::FfmpegInterpolator;
+
 mod fps;
+pub use fps::Target;
 pub use fps::FPS;
 
+mod frame_accountant;
+pub use frame_accountant::FrameAccountant;
+pub use frame_accountant::FramePlanner;
+
 mod frame_generator;
 pub use frame_generator::FrameGenerator;
 
+pub mod interpolator;
+pub use interpolator::Interpolation;
+pub use interpolator::Interpolator;
+
+pub mod manifest;
+pub use manifest::Manifest;
+
+pub mod pipeline;
+pub use pipeline::Pipeline;
+
+pub mod progress;
+pub use progress::Progress;
+
 mod non_zero_decimal;
 pub use non_zero_decimal::NonZeroDecimal;
 
-mod time_ranges;
+pub mod scenes;
+
+pub mod time_ranges;
 pub use time_ranges::TimeRange;
 pub use time_ranges::TimeRanges;
 
+pub mod window;
+
 mod reusable_temp_dir;
 pub use reusable_temp_dir::ResetData;
 pub use reusable_temp_dir::ReusableTempDir;
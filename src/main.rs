@@ -1,60 +1,635 @@
 use clap::Parser;
-use log::{debug, info};
+use log::{debug, info, warn};
+use more_fps::command;
+use more_fps::cuts::CutSource;
+use more_fps::ffmpeg;
+use more_fps::ffmpeg::RawFormat;
+use more_fps::manifest::ChunkStatus;
+use more_fps::manifest::InputIdentity;
+use more_fps::manifest::SceneSettings;
+use more_fps::manifest::Staleness;
+use more_fps::manifest::VideoSettings;
+use more_fps::pipeline::Job;
+use more_fps::pipeline::Treatment;
+use more_fps::scenes::Scenes;
+use more_fps::time_ranges;
+use more_fps::window;
+use more_fps::window::Outside;
 use more_fps::Cli;
+use more_fps::Command;
+use more_fps::DiskBudget;
 use more_fps::Error;
+use more_fps::FfmpegInterpolator;
 use more_fps::FfmpegStepper;
-use more_fps::FrameGenerator;
+use more_fps::FrameAccountant;
+use more_fps::FramePlanner;
+use more_fps::Interpolation;
+use more_fps::Manifest;
+use more_fps::Pipeline;
+use more_fps::Progress;
+use more_fps::Rerender;
+use more_fps::ResetData;
 use more_fps::ReusableTempDir;
+use more_fps::TimeRange;
+use more_fps::FPS;
+use std::num::NonZeroU64;
+use std::ops::Range;
+use std::process::ExitCode;
+use std::sync::Mutex;
 
-fn main() -> Result<(), Error> {
+/// What shells use for a process stopped by SIGINT
+const INTERRUPTED_EXIT_CODE: u8 = 130;
+
+fn main() -> ExitCode {
     env_logger::init();
     let args = Cli::parse();
     debug!("{args:?}");
 
-    let temp_dir = ReusableTempDir::try_new(args.temp_dir, args.reset)?;
+    // the manifest is saved after every chunk, so stopping the commands is enough to be able to
+    // continue later
+    let handler = ctrlc::set_handler(|| {
+        if command::interrupted() {
+            // pressed twice, the commands were killed the first time
+            std::process::exit(INTERRUPTED_EXIT_CODE.into());
+        }
+        warn!("Stopping, press Ctrl+C again to exit right away");
+        command::interrupt();
+    });
+    if let Err(e) = handler {
+        warn!("Unable to handle Ctrl+C: {e}");
+    }
+
+    match run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Interrupted) => {
+            eprintln!("{}", Error::Interrupted);
+            ExitCode::from(INTERRUPTED_EXIT_CODE)
+        }
+        Err(e) => {
+            eprintln!("Error: {e}");
+            if let Some(log_file) = command::log_file().filter(|log_file| log_file.exists()) {
+                eprintln!("The output of every failed command is in {log_file:?}");
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Cli) -> Result<(), Error> {
+    // the arguments are checked before the temp dir is reset, so a typo can't wipe a previous
+    // run that could have been continued
+    let interpolators = args.interpolators()?;
+    let fallback = args.fallback_interpolator()?;
+    // every worker uses the same backend
+    let interpolator = &interpolators[0];
+    let encoder = args.encoder();
+    let chunk_encoder = args.chunk_encoder();
+    let final_encoder = args.lossless_chunks.map(|_| encoder.clone());
+    // the output's video stream is either encoded with or stream copied from this codec
+    let output_extension = ffmpeg::get_extension(&args.output)?;
+    if !encoder.codec.supports_container(output_extension) {
+        return Err(Error::UnsupportedContainer(
+            encoder.codec,
+            output_extension.to_owned(),
+        ));
+    }
+    // only mkv can hold the two frame rates
+    if args.outside == Outside::Original && !args.windows().is_empty() && output_extension != "mkv"
+    {
+        return Err(Error::VariableFrameRateContainer(
+            output_extension.to_owned(),
+        ));
+    }
+    let cut_sources = args.cut_sources()?;
+
+    // rerendering needs the previous run's chunks
+    let reset = match args.command {
+        Some(Command::Rerender(_)) => ResetData::Nothing,
+        None => args.reset,
+    };
+    let temp_dir = ReusableTempDir::try_new(args.temp_dir.clone(), reset)?;
+    command::set_log_file(temp_dir.log_file().clone());
+    command::set_timeouts(args.timeouts());
+
+    let target = args.target();
+    let source_fps = ffmpeg::frame_rate(&args.input)?;
+    let fps = target.output_fps(source_fps)?;
+    info!("Creating a {fps} fps video from a {source_fps} fps video");
+
+    // how big each frame is, for streaming + estimating the temp dir's size
+    let raw_format = ffmpeg::raw_format(&args.input)?;
+    let stream = match (args.stream, interpolator.can_stream()) {
+        (true, false) => {
+            warn!(
+                "{} can't stream frames, using png files instead",
+                interpolator.name()
+            );
+            false
+        }
+        (stream, _) => stream,
+    };
+    let disk_budget = DiskBudget::new(args.max_temp_bytes, &raw_format);
+
+    // streamed frames never touch the temp dir
+    let max_chunk_frames = match stream {
+        true => None,
+        false => disk_budget.max_chunk_frames(
+            source_fps,
+            fps,
+            Pipeline::chunks_in_flight(interpolators.len()),
+        )?,
+    };
+    if let Some(max_chunk_frames) = max_chunk_frames {
+        debug!("Chunks are at most {max_chunk_frames} frames to fit in --max-temp-bytes");
+    }
+
+    let input = InputIdentity::try_from(args.input.as_path())?;
+    let scene_detection = args.scene_detection(source_fps)?;
+    let scene_settings = SceneSettings {
+        scene_gt: args.scene_gt.clone(),
+        min_scene_length: args.min_scene_length,
+        max_scene_length: args.max_scene_length,
+        scene_hysteresis: args.scene_hysteresis,
+        adaptive: scene_detection.adaptive,
+        fades: scene_detection.fades,
+        cuts: cut_sources.clone(),
+        cut_file: args
+            .cut_file
+            .as_deref()
+            .map(InputIdentity::try_from)
+            .transpose()?,
+        windows: args.windows(),
+        outside: args.outside,
+        max_chunk_frames,
+    };
+    let video_settings = video_settings(&args, fps);
+
     let ffmpeg_stepper = FfmpegStepper::try_new(
         temp_dir.ffmpeg_dir(),
-        args.input,
-        args.crf,
-        args.fps.non_zero_usize(),
+        args.input.clone(),
+        chunk_encoder,
+        final_encoder,
+        fps,
+        source_fps,
     )?;
 
-    let frame_generator = FrameGenerator {
-        binary: &args.ai_binary,
-        model: &args.ai_model,
-        fps: args.fps,
-        input_dir: ffmpeg_stepper.frames_dir(),
-        extra_args: &args.ai_args,
-        output_dir: temp_dir.generated_frames_dir(),
+    let worker_dirs = (0..interpolators.len())
+        .map(|worker| temp_dir.generated_frames_dir().join(worker.to_string()))
+        .collect::<Vec<_>>();
+    let workers = interpolators
+        .iter()
+        .zip(&worker_dirs)
+        .map(|(interpolator, worker_dir)| {
+            Interpolation::try_new(
+                interpolator.as_ref(),
+                target,
+                ffmpeg_stepper.frames_dir(),
+                worker_dir,
+            )
+            .map(|interpolation| {
+                interpolation.with_retries(args.retry_policy(), fallback.as_deref())
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if let Some(Command::Rerender(rerender)) = &args.command {
+        return self::rerender(
+            rerender,
+            &args,
+            &temp_dir,
+            &ffmpeg_stepper,
+            &workers,
+            stream.then_some(&raw_format),
+            disk_budget,
+        );
+    }
+
+    // a previous run with different settings can't be reused
+    let previous_manifest = temp_dir.load_manifest()?;
+    let staleness = match &previous_manifest {
+        Some(previous) => previous.staleness(&input, &scene_settings, &video_settings),
+        // we can't tell what created the existing data
+        None => Staleness::Everything,
     };
+    if staleness == Staleness::Everything {
+        ffmpeg_stepper.clear_scene_cache()?;
+    }
+    if staleness != Staleness::Fresh {
+        debug!("Previous run can't be reused: {staleness:?}");
+        ffmpeg_stepper.clear_videos_dir()?;
+    }
 
     info!("Extracting scene data to file...");
-    let time_ranges = ffmpeg_stepper
-        .flattened_time_ranges(args.max_step_size, &args.scene_gt)?
-        .into_iter()
-        .enumerate();
-    let existing_video_count = ffmpeg_stepper.existing_video_count()?;
-
-    info!("Beginning extraction + video creation process");
-    for (index, time_range) in time_ranges.skip(existing_video_count) {
-        let duration = time_range.duration();
-
-        ffmpeg_stepper.extract_frames(&time_range)?;
-        let generated_frames_dir = frame_generator.execute(duration)?.to_owned();
-        ffmpeg_stepper.frames_to_video(index, generated_frames_dir)?;
+    let scenes = ffmpeg_stepper.scenes(
+        &scene_detection,
+        &cut_sources,
+        args.cut_file.as_deref(),
+        max_chunk_frames,
+        &scene_settings.windows,
+        scene_settings.outside == Outside::Clip,
+    )?;
+    if !scenes.fades.is_empty() {
         info!(
-            "Extracted a total of {} seconds",
-            time_range.start + *duration
+            "Found {} fades, their frames are duplicated instead of interpolated",
+            scenes.fades.len()
         );
-        //pause();
     }
+    let end = scenes
+        .time_ranges
+        .last()
+        .map_or(0, |time_range| time_range.end());
+    let windows = window::frame_ranges(&scene_settings.windows, &source_fps, end)?;
+    let in_window = |time_range: &TimeRange| in_window(&windows, time_range);
+    // the chunks end where the windows do
+    let bounds = windows
+        .iter()
+        .flat_map(|frames| [frames.start, frames.end])
+        .collect::<Vec<_>>();
+    let mut time_ranges = time_ranges::split_at(&scenes.time_ranges, &bounds);
+    if scene_settings.outside == Outside::Clip {
+        time_ranges.retain(in_window);
+    }
+    let treatments = time_ranges
+        .iter()
+        .map(|time_range| treatment(time_range, &scenes, &windows, scene_settings.outside))
+        .collect::<Vec<_>>();
+    let time_ranges = &time_ranges;
+    let clip = (scene_settings.outside == Outside::Clip && !scene_settings.windows.is_empty())
+        .then_some(&windows);
+
+    let mut manifest = Manifest::new(input, scene_settings, video_settings, time_ranges);
+    if let (Staleness::Fresh, Some(previous)) = (staleness, previous_manifest) {
+        if !manifest.resume_from(previous) {
+            debug!("The previous run split the video differently");
+            ffmpeg_stepper.clear_videos_dir()?;
+        }
+    }
+    temp_dir.save_manifest(&manifest)?;
+    // every chunk's frame count is estimated up front, the workers get the actual count from
+    // the planner when they start on it (see `FramePlanner`)
+    let mut estimator = FrameAccountant::new(fps, source_fps, 0);
+    let mut estimated = 0;
+    let mut kept = vec![];
+    let mut jobs = vec![];
+    for (index, (time_range, &treatment)) in time_ranges.iter().zip(&treatments).enumerate() {
+        // the parts of the input that aren't in the clip
+        estimator.skip(estimated..time_range.start);
+        estimated = time_range.end();
+        let (frame_count, frame_rate) = match treatment {
+            Treatment::Original => {
+                estimator.skip(time_range.start..time_range.end());
+                let frame_count = time_range.frame_count().get() as u64;
+                (NonZeroU64::new(frame_count).unwrap(), source_fps)
+            }
+            _ => (workers[0].expected_frame_count(time_range, &estimator), fps),
+        };
+        // videos from a previous run, which may have been planned differently
+        let done_frames = match manifest.chunks[index].status {
+            ChunkStatus::Done { frames } => frames,
+            _ => frame_count.get(),
+        };
+        let done = ffmpeg_stepper.is_valid_video(index, done_frames, &frame_rate);
+        match (treatment, done) {
+            (Treatment::Original, _) => kept.push((*time_range, None)),
+            (_, true) => {
+                estimator.record(done_frames);
+                kept.push((*time_range, Some(done_frames)));
+            }
+            (_, false) => estimator.record(frame_count.get()),
+        }
+        if done {
+            debug!("Video {index} already exists, skipping it");
+            manifest.set_status(
+                index,
+                ChunkStatus::Done {
+                    frames: done_frames,
+                },
+            );
+        } else {
+            ffmpeg_stepper.remove_video(index)?;
+            manifest.set_status(index, ChunkStatus::Pending);
+            jobs.push(Job {
+                index,
+                time_range: *time_range,
+                frame_count,
+                treatment,
+            });
+        }
+    }
+    let planner = Mutex::new(FramePlanner::new(fps, source_fps, kept));
+    temp_dir.save_manifest(&manifest)?;
+    // frames left behind by a cancelled run
     ffmpeg_stepper.clear_frames_dir()?;
-    frame_generator.clear_output_dir()?;
+    for worker in &workers {
+        worker.clear_output_dir()?;
+    }
+
+    let total_frames = time_ranges
+        .iter()
+        .map(|time_range| time_range.frame_count().get())
+        .sum();
+    let progress = Progress::new(args.progress, source_fps, time_ranges.len(), total_frames);
+    let resumed = time_ranges
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| manifest.is_done(*index))
+        .map(|(_, time_range)| time_range.frame_count().get())
+        .collect::<Vec<_>>();
+    progress.resume(resumed.len(), resumed.iter().sum());
+
+    info!(
+        "Beginning extraction + video creation process with {} worker(s)",
+        workers.len()
+    );
+    let pipeline = Pipeline {
+        ffmpeg_stepper: &ffmpeg_stepper,
+        workers: &workers,
+        passthrough: &FfmpegInterpolator::Duplicate,
+        raw_format: stream.then_some(&raw_format),
+        disk_budget,
+        progress: &progress,
+        planner: &planner,
+    };
+    let mut failed_chunks = vec![];
+    let result = pipeline.run(jobs, |index, frames| {
+        match frames {
+            Ok(frames) => {
+                manifest.set_status(index, ChunkStatus::Done { frames });
+                progress.chunk_done(time_ranges[index].frame_count().get());
+            }
+            Err(e) => {
+                warn!("Giving up on chunk {index}: {e}");
+                manifest.set_status(
+                    index,
+                    ChunkStatus::Failed {
+                        error: e.to_string(),
+                    },
+                );
+                failed_chunks.push(index);
+            }
+        }
+        temp_dir.save_manifest(&manifest)
+    });
+    progress.finish();
+    result?;
+    if !failed_chunks.is_empty() {
+        failed_chunks.sort_unstable();
+        return Err(Error::FailedChunks(failed_chunks));
+    }
+
+    let mut accountant = FrameAccountant::new(fps, source_fps, 0);
+    let mut accounted = 0;
+    let mut created = 0;
+    for (chunk, treatment) in manifest.chunks.iter().zip(&treatments) {
+        accountant.skip(accounted..chunk.time_range.start);
+        accounted = chunk.time_range.end();
+        let ChunkStatus::Done { frames } = chunk.status else {
+            continue;
+        };
+        created += frames;
+        match treatment {
+            Treatment::Original => accountant.skip(chunk.time_range.start..accounted),
+            _ => accountant.record(frames),
+        }
+    }
+    match accountant.drift(accounted) {
+        0 => info!("Created exactly {created} frames"),
+        drift => warn!("Final video is off by {drift} frames"),
+    }
+    ffmpeg_stepper.clear_frames_dir()?;
+    for worker in &workers {
+        worker.clear_output_dir()?;
+    }
 
     info!("Finished extracting ALL frames, now creating the final video");
-    ffmpeg_stepper.aggregate(&args.output)?;
+    ffmpeg_stepper.aggregate(&args.output, time_ranges.len(), clip.map(Vec::as_slice))?;
+
+    match args.keep_temp_dir {
+        true => info!("Keeping {:?} so chunks can be rerendered", args.temp_dir),
+        false => temp_dir.delete()?,
+    }
+    Ok(())
+}
+
+/// Whether all of the `time_range` is in one of the `windows` (see `window::frame_ranges`)
+fn in_window(windows: &[Range<usize>], time_range: &TimeRange) -> bool {
+    windows
+        .iter()
+        .any(|frames| frames.start <= time_range.start && time_range.end() <= frames.end)
+}
+
+/// How a chunk's video is created
+fn treatment(
+    time_range: &TimeRange,
+    scenes: &Scenes,
+    windows: &[Range<usize>],
+    outside: Outside,
+) -> Treatment {
+    match (in_window(windows, time_range), outside) {
+        (true, _) if scenes.is_fade(time_range) => Treatment::Duplicate,
+        (true, _) => Treatment::Interpolate,
+        (false, Outside::Duplicate) => Treatment::Duplicate,
+        // clips don't have anything outside of the windows
+        (false, _) => Treatment::Original,
+    }
+}
+
+/// The settings the chunks' videos are created with
+fn video_settings(args: &Cli, fps: FPS) -> VideoSettings {
+    VideoSettings {
+        target: args.target(),
+        fps,
+        chunk_encoder: args.chunk_encoder(),
+        backend: args.backend,
+        ai_template: args.ai_template.clone(),
+        ai_stream_template: args.ai_stream_template.clone(),
+        ai_binary: args.ai_binary.clone(),
+        ai_model: args.ai_model.clone(),
+        ai_fixed_multiplier: args.ai_fixed_multiplier,
+        ai_output_glob: args.ai_output_glob.clone(),
+        ai_args: args.ai_args.clone(),
+        workers: args.workers.clone(),
+    }
+}
+
+/// Creates the chunks' videos again with the current settings (but the previous run's chunks),
+/// then writes the output again
+fn rerender(
+    rerender: &Rerender,
+    args: &Cli,
+    temp_dir: &ReusableTempDir,
+    ffmpeg_stepper: &FfmpegStepper,
+    workers: &[Interpolation],
+    raw_format: Option<&RawFormat>,
+    disk_budget: DiskBudget,
+) -> Result<(), Error> {
+    let Some(mut manifest) = temp_dir.load_manifest()? else {
+        return Err(Error::NothingToRerender(args.temp_dir.clone()));
+    };
+    if manifest.input != InputIdentity::try_from(args.input.as_path())? {
+        return Err(Error::InputChanged(args.input.clone()));
+    }
+    let source_fps = ffmpeg::frame_rate(&args.input)?;
+    let fps = args.target().output_fps(source_fps)?;
+    let video_settings = video_settings(args, fps);
+    if let Some(setting) = manifest.video_settings.rerender_conflict(&video_settings) {
+        return Err(Error::RerenderConflict(setting));
+    }
+    let chunk_count = manifest.chunks.len();
+
+    let mut indices = rerender.chunks.clone();
+    for time in &rerender.at {
+        let frame = source_fps.frame_at(time)?;
+        let index = manifest
+            .chunks
+            .iter()
+            .position(|chunk| chunk.time_range.start <= frame && frame < chunk.time_range.end())
+            .ok_or(Error::NoChunkAt(*time))?;
+        indices.push(index);
+    }
+    indices.sort_unstable();
+    indices.dedup();
+    if let Some(&index) = indices.iter().find(|&&index| index >= chunk_count) {
+        return Err(Error::InvalidChunk(index, chunk_count));
+    }
+
+    // the chunks are created like `run` would with the current settings, so a chunk can be
+    // split up or become a fade
+    let scene_settings = &manifest.scene_settings;
+    let previous_detection = scene_settings.scene_detection(source_fps)?;
+    let mut scene_detection = args.scene_detection(source_fps)?;
+    let split = scene_detection != previous_detection;
+    if let Some(max_chunk_frames) = scene_settings.max_chunk_frames {
+        scene_detection.max_frames = scene_detection.max_frames.min(max_chunk_frames);
+    }
+    let scores = match scene_settings.cuts.contains(&CutSource::Detected) {
+        true => ffmpeg_stepper.scene_scores(&scene_settings.windows)?,
+        false => vec![],
+    };
+    let end = manifest
+        .chunks
+        .last()
+        .map_or(0, |chunk| chunk.time_range.end());
+    let windows = window::frame_ranges(&scene_settings.windows, &source_fps, end)?;
+    // numbered after the last chunk, so the old videos are kept until the new ones are done
+    let mut pieces = vec![];
+    let mut replaced = vec![];
+    for &index in &indices {
+        let time_range = manifest.chunks[index].time_range;
+        let end = time_range.frame_count().saturating_add(time_range.start);
+        let scenes = scene_detection.scenes_in(&scores, &[], time_range.start, end)?;
+        // with different scene settings, the chunk is split up at the scenes found inside it
+        let split_up = match split {
+            true => scenes.time_ranges.clone(),
+            false => vec![time_range],
+        };
+        // the pieces add up to the frames the chunk had, which the chunks after it are in sync
+        // with
+        let mut estimator = match manifest.chunks[index].status {
+            ChunkStatus::Done { frames } => {
+                replaced.push((time_range, frames));
+                FrameAccountant::replacing(fps, source_fps, &time_range, frames)
+            }
+            _ => FrameAccountant::new(fps, source_fps, time_range.start),
+        };
+        for piece in split_up {
+            let treatment = treatment(&piece, &scenes, &windows, scene_settings.outside);
+            let frame_count = match treatment {
+                Treatment::Original => {
+                    estimator.skip(piece.start..piece.end());
+                    NonZeroU64::new(piece.frame_count().get() as u64).unwrap()
+                }
+                _ => {
+                    let frame_count = workers[0].expected_frame_count(&piece, &estimator);
+                    estimator.record(frame_count.get());
+                    frame_count
+                }
+            };
+            let job = Job {
+                index: chunk_count + pieces.len(),
+                time_range: piece,
+                frame_count,
+                treatment,
+            };
+            pieces.push((index, job));
+        }
+    }
+    info!(
+        "Rerendering chunks {indices:?} as {} chunk(s) with {} worker(s)",
+        pieces.len(),
+        workers.len()
+    );
+
+    let total_frames = pieces
+        .iter()
+        .map(|(_, job)| job.time_range.frame_count().get())
+        .sum();
+    let progress = Progress::new(args.progress, source_fps, pieces.len(), total_frames);
+    let pipeline = Pipeline {
+        ffmpeg_stepper,
+        workers,
+        passthrough: &FfmpegInterpolator::Duplicate,
+        raw_format,
+        disk_budget,
+        progress: &progress,
+        planner: &Mutex::new(FramePlanner::new(fps, source_fps, vec![]).with_replaced(replaced)),
+    };
+    let mut created = vec![None; pieces.len()];
+    let mut failed_chunks = vec![];
+    let jobs = pieces.iter().map(|(_, job)| *job).collect();
+    let result = pipeline.run(jobs, |piece_index, frames| {
+        let (index, job) = &pieces[piece_index - chunk_count];
+        match frames {
+            Ok(frames) => {
+                created[piece_index - chunk_count] = Some(frames);
+                progress.chunk_done(job.time_range.frame_count().get());
+            }
+            Err(e) => {
+                warn!("Giving up on chunk {index}: {e}");
+                failed_chunks.push(*index);
+            }
+        }
+        Ok(())
+    });
+    progress.finish();
+    result?;
+
+    for &index in &indices {
+        let frames = pieces
+            .iter()
+            .zip(&created)
+            .filter(|((piece_of, _), _)| *piece_of == index)
+            .map(|((_, job), frames)| frames.map(|frames| (job.index, frames)))
+            .collect::<Option<Vec<_>>>();
+        // failed
+        let Some(frames) = frames else {
+            continue;
+        };
+        let piece_indices = frames.iter().map(|(piece, _)| *piece).collect::<Vec<_>>();
+        ffmpeg_stepper.replace_video(index, &piece_indices)?;
+        manifest.set_status(
+            index,
+            ChunkStatus::Done {
+                frames: frames.iter().map(|(_, frames)| frames).sum(),
+            },
+        );
+        temp_dir.save_manifest(&manifest)?;
+    }
+    ffmpeg_stepper.clear_frames_dir()?;
+    for worker in workers {
+        worker.clear_output_dir()?;
+    }
+    if !failed_chunks.is_empty() {
+        failed_chunks.sort_unstable();
+        failed_chunks.dedup();
+        return Err(Error::FailedRerender(failed_chunks));
+    }
 
-    temp_dir.delete()?;
+    info!("Finished rerendering, now creating the final video");
+    let scene_settings = &manifest.scene_settings;
+    let clip = (scene_settings.outside == Outside::Clip && !scene_settings.windows.is_empty())
+        .then_some(windows.as_slice());
+    ffmpeg_stepper.aggregate(&args.output, chunk_count, clip)?;
     Ok(())
 }
 
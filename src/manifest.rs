@@ -0,0 +1,403 @@
+use crate::cuts::CutSource;
+use crate::interpolator::Backend;
+use crate::scenes::AdaptiveThreshold;
+use crate::scenes::FadeDetection;
+use crate::scenes::SceneDetection;
+use crate::window::Outside;
+use crate::window::Window;
+use crate::Encoder;
+use crate::Error;
+use crate::Target;
+use crate::TimeRange;
+use crate::FPS;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serialize;
+use std::fs;
+use std::num::NonZeroU64;
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// Everything we need to know to safely continue a previous run
+/// Stored as json in the temp dir, see `ReusableTempDir::save_manifest`
+/// Every field is required (even the `Option`s, see `required`), so a manifest from another
+/// version isn't read with some of the settings missing
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub input: InputIdentity,
+    pub scene_settings: SceneSettings,
+    pub video_settings: VideoSettings,
+    pub chunks: Vec<Chunk>,
+}
+
+/// For `Option` fields, which serde would otherwise read as `None` when they're missing
+pub(crate) fn required<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer)
+}
+
+/// Used to figure out if the input file changed between runs
+/// Hashing a multi-gigabyte video would take too long, so we rely on the size + modified time
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputIdentity {
+    pub path: PathBuf,
+    pub size: u64,
+    /// seconds since the unix epoch (if the platform supports it)
+    #[serde(deserialize_with = "required")]
+    pub modified: Option<u64>,
+}
+
+impl TryFrom<&Path> for InputIdentity {
+    type Error = Error;
+    fn try_from(path: &Path) -> Result<Self, Self::Error> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+        Ok(Self {
+            path: fs::canonicalize(path)?,
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// Settings that change where the chunks start/end
+/// The cached scene scores don't depend on these, see `SceneDetection`
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SceneSettings {
+    pub scene_gt: String,
+    /// seconds
+    pub min_scene_length: Decimal,
+    /// seconds
+    pub max_scene_length: Decimal,
+    #[serde(deserialize_with = "required")]
+    pub scene_hysteresis: Option<Decimal>,
+    #[serde(deserialize_with = "required")]
+    pub adaptive: Option<AdaptiveThreshold>,
+    #[serde(deserialize_with = "required")]
+    pub fades: Option<FadeDetection>,
+    pub cuts: Vec<CutSource>,
+    /// a different edit means different cuts
+    #[serde(deserialize_with = "required")]
+    pub cut_file: Option<InputIdentity>,
+    /// none means the whole input
+    pub windows: Vec<Window>,
+    pub outside: Outside,
+    /// from --max-temp-bytes (and the number of workers), see `DiskBudget::max_chunk_frames`
+    #[serde(deserialize_with = "required")]
+    pub max_chunk_frames: Option<NonZeroUsize>,
+}
+
+impl SceneSettings {
+    /// The `SceneDetection` the chunks were found with, see `Cli::scene_detection`
+    pub fn scene_detection(&self, source_fps: FPS) -> Result<SceneDetection, Error> {
+        let max_frames = source_fps.frame_at(&self.max_scene_length)?;
+        Ok(SceneDetection {
+            threshold: Decimal::from_str_exact(&self.scene_gt)?,
+            min_frames: source_fps.frame_at(&self.min_scene_length)?,
+            max_frames: NonZeroUsize::new(max_frames).unwrap_or(NonZeroUsize::MIN),
+            hysteresis: self.scene_hysteresis,
+            adaptive: self.adaptive,
+            fades: self.fades,
+        })
+    }
+}
+
+/// Settings that change the content of each chunk's video
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct VideoSettings {
+    pub target: Target,
+    pub fps: FPS,
+    /// the final encode doesn't change the chunks, so this is only the chunk encoder
+    pub chunk_encoder: Encoder,
+    pub backend: Backend,
+    #[serde(deserialize_with = "required")]
+    pub ai_template: Option<String>,
+    #[serde(deserialize_with = "required")]
+    pub ai_stream_template: Option<String>,
+    #[serde(deserialize_with = "required")]
+    pub ai_binary: Option<PathBuf>,
+    #[serde(deserialize_with = "required")]
+    pub ai_model: Option<PathBuf>,
+    #[serde(deserialize_with = "required")]
+    pub ai_fixed_multiplier: Option<NonZeroU64>,
+    pub ai_output_glob: String,
+    pub ai_args: String,
+    /// each worker's args replace `ai_args`
+    pub workers: Vec<String>,
+}
+
+impl VideoSettings {
+    /// The setting a rerender can't change, if `current` changed one
+    /// The rerendered chunks go back in with the others, so only the interpolator's settings
+    /// can be different
+    pub fn rerender_conflict(&self, current: &VideoSettings) -> Option<&'static str> {
+        if self.target != current.target {
+            Some("target frame rate")
+        } else if self.fps != current.fps {
+            Some("frame rate")
+        } else if self.chunk_encoder != current.chunk_encoder {
+            Some("chunk encoder")
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Chunk {
+    #[serde(flatten)]
+    pub time_range: TimeRange,
+    pub status: ChunkStatus,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStatus {
+    Pending,
+    /// The chunk's video was created with this many frames
+    Done {
+        frames: u64,
+    },
+    /// The interpolator kept failing, so it's tried again by the next run
+    Failed {
+        error: String,
+    },
+}
+
+/// How much of a previous run can be reused
+#[derive(Debug, PartialEq)]
+pub enum Staleness {
+    /// Nothing changed
+    Fresh,
+    /// The scenes are still valid, but every chunk's video has to be created again
+    Videos,
+    /// The scene scores are still valid, but the video is split up differently, so every
+    /// chunk's video has to be created again
+    Scenes,
+    /// The input changed, so nothing can be reused
+    Everything,
+}
+
+impl Manifest {
+    pub fn new(
+        input: InputIdentity,
+        scene_settings: SceneSettings,
+        video_settings: VideoSettings,
+        time_ranges: &[TimeRange],
+    ) -> Self {
+        let chunks = time_ranges
+            .iter()
+            .map(|time_range| Chunk {
+                time_range: *time_range,
+                status: ChunkStatus::Pending,
+            })
+            .collect();
+        Self {
+            input,
+            scene_settings,
+            video_settings,
+            chunks,
+        }
+    }
+
+    /// Compares a previous run's manifest with the current run
+    pub fn staleness(
+        &self,
+        input: &InputIdentity,
+        scene_settings: &SceneSettings,
+        video_settings: &VideoSettings,
+    ) -> Staleness {
+        if self.input != *input {
+            Staleness::Everything
+        } else if self.scene_settings != *scene_settings {
+            Staleness::Scenes
+        } else if self.video_settings != *video_settings {
+            Staleness::Videos
+        } else {
+            Staleness::Fresh
+        }
+    }
+
+    /// Keeps the status of `previous`'s chunks, as long as it split the video the same way
+    /// Returns false if it didn't, in which case its videos are for the wrong chunks
+    pub fn resume_from(&mut self, previous: Manifest) -> bool {
+        let same_time_ranges = self.chunks.len() == previous.chunks.len()
+            && self
+                .chunks
+                .iter()
+                .zip(&previous.chunks)
+                .all(|(chunk, previous)| chunk.time_range == previous.time_range);
+        if same_time_ranges {
+            self.chunks = previous.chunks;
+        }
+        same_time_ranges
+    }
+
+    pub fn is_done(&self, index: usize) -> bool {
+        matches!(
+            self.chunks.get(index).map(|chunk| &chunk.status),
+            Some(ChunkStatus::Done { .. })
+        )
+    }
+
+    pub fn set_status(&mut self, index: usize, status: ChunkStatus) {
+        if let Some(chunk) = self.chunks.get_mut(index) {
+            chunk.status = status;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoder::Codec;
+    use crate::TimeRanges;
+
+    fn manifest() -> Manifest {
+        let time_ranges = TimeRanges::try_new(0, 240, 1000)
+            .unwrap()
+            .collect::<Vec<_>>();
+        Manifest::new(
+            InputIdentity {
+                path: PathBuf::from("/videos/input.mkv"),
+                size: 1234,
+                modified: Some(1682000000),
+            },
+            SceneSettings {
+                scene_gt: String::from(".1"),
+                min_scene_length: Decimal::ZERO,
+                max_scene_length: Decimal::from(50),
+                scene_hysteresis: None,
+                adaptive: None,
+                fades: None,
+                cuts: vec![CutSource::Detected],
+                cut_file: None,
+                windows: vec![],
+                outside: Outside::Clip,
+                max_chunk_frames: None,
+            },
+            VideoSettings {
+                target: Target::Multiplier(NonZeroU64::new(2).unwrap()),
+                fps: "48000/1001".parse().unwrap(),
+                chunk_encoder: Encoder {
+                    codec: Codec::Libx264,
+                    preset: None,
+                    crf: NonZeroUsize::new(18).unwrap(),
+                    lossless: false,
+                    pix_fmt: String::from("yuv420p"),
+                    extra_args: String::new(),
+                },
+                backend: Backend::Rife,
+                ai_template: None,
+                ai_stream_template: None,
+                ai_binary: Some(PathBuf::from("rife-ncnn-vulkan")),
+                ai_model: Some(PathBuf::from("models/rife-v4.6")),
+                ai_fixed_multiplier: None,
+                ai_output_glob: String::from("*.png"),
+                ai_args: String::from("-g 0,-1"),
+                workers: vec![],
+            },
+            &time_ranges,
+        )
+    }
+
+    #[test]
+    fn json_round_trip() {
+        let mut expected = manifest();
+        expected.set_status(1, ChunkStatus::Done { frames: 480 });
+        let json = serde_json::to_string(&expected).unwrap();
+        let actual: Manifest = serde_json::from_str(&json).unwrap();
+        assert_eq!(actual, expected);
+        assert!(actual.is_done(1));
+        assert!(!actual.is_done(0));
+    }
+
+    #[test]
+    fn missing_settings() {
+        let json = serde_json::to_string(&manifest()).unwrap();
+        let json = json.replace(r#""scene_hysteresis":null,"#, "");
+        assert!(serde_json::from_str::<Manifest>(&json).is_err());
+    }
+
+    #[test]
+    fn staleness() {
+        let previous = manifest();
+        let current = manifest();
+        assert_eq!(
+            previous.staleness(
+                &current.input,
+                &current.scene_settings,
+                &current.video_settings
+            ),
+            Staleness::Fresh
+        );
+
+        // the worker args are used instead of --ai-args
+        let mut video_settings = manifest().video_settings;
+        video_settings.workers = vec![String::from("-g 1")];
+        assert_eq!(
+            previous.staleness(&current.input, &current.scene_settings, &video_settings),
+            Staleness::Videos
+        );
+
+        let mut video_settings = manifest().video_settings;
+        video_settings.chunk_encoder.crf = NonZeroUsize::new(20).unwrap();
+        assert_eq!(
+            previous.staleness(&current.input, &current.scene_settings, &video_settings),
+            Staleness::Videos
+        );
+
+        let mut scene_settings = manifest().scene_settings;
+        scene_settings.scene_gt = String::from(".2");
+        assert_eq!(
+            previous.staleness(&current.input, &scene_settings, &video_settings),
+            Staleness::Scenes
+        );
+
+        let mut input = manifest().input;
+        input.size += 1;
+        assert_eq!(
+            previous.staleness(&input, &scene_settings, &video_settings),
+            Staleness::Everything
+        );
+    }
+
+    #[test]
+    fn rerender_conflicts() {
+        let previous = manifest().video_settings;
+        let mut current = manifest().video_settings;
+        current.ai_args = String::from("-g 1");
+        current.workers = vec![String::from("-g 0"), String::from("-g 1")];
+        assert_eq!(previous.rerender_conflict(&current), None);
+
+        current.chunk_encoder.crf = NonZeroUsize::new(20).unwrap();
+        assert_eq!(previous.rerender_conflict(&current), Some("chunk encoder"));
+    }
+
+    #[test]
+    fn resume_needs_same_time_ranges() {
+        let mut previous = manifest();
+        previous.set_status(0, ChunkStatus::Done { frames: 480 });
+
+        let mut current = manifest();
+        assert!(current.resume_from(previous));
+        assert!(current.is_done(0));
+
+        let mut previous = manifest();
+        previous.set_status(0, ChunkStatus::Done { frames: 480 });
+        previous.chunks.pop();
+        let mut current = manifest();
+        assert!(!current.resume_from(previous));
+        assert!(!current.is_done(0));
+    }
+}
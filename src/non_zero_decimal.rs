@@ -43,7 +43,7 @@ mod tests {
     #[test]
     fn non_zero_decimal() {
         assert_eq!(
-            NonZeroDecimal::try_new(12).map(|d| d.get().clone()),
+            NonZeroDecimal::try_new(12).map(|d| *d.get()),
             Some(Decimal::from_str_exact("12").unwrap())
         );
     }
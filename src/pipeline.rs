@@ -0,0 +1,365 @@
+use crate::ffmpeg::RawFormat;
+use crate::progress::Progress;
+use crate::progress::Stage;
+use crate::DiskBudget;
+use crate::Error;
+use crate::FfmpegStepper;
+use crate::FramePlanner;
+use crate::Interpolation;
+use crate::Interpolator;
+use crate::TimeRange;
+use log::debug;
+use log::warn;
+use std::fs;
+use std::num::NonZeroU64;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::SyncSender;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+/// Creates the video for each chunk
+/// Extracting, interpolating and encoding run on their own threads, so chunk N+1 is extracted
+/// and chunk N-1 is encoded while chunk N is interpolated (and the GPU doesn't sit idle)
+/// Each worker interpolates one chunk at a time, so several GPUs can be used at once
+/// The channels between the stages don't buffer anything, so only a few chunks' frames are on
+/// disk at once
+#[derive(Debug)]
+pub struct Pipeline<'a> {
+    pub ffmpeg_stepper: &'a FfmpegStepper,
+    /// One per worker, each with its own interpolator (device args) + output directory
+    pub workers: &'a [Interpolation<'a>],
+    /// Used instead of the workers' interpolators for `Treatment::Duplicate` chunks
+    pub passthrough: &'a dyn Interpolator,
+    /// If set, each chunk is streamed instead (see `FfmpegStepper::stream_chunk`)
+    pub raw_format: Option<&'a RawFormat>,
+    /// Used to check there's enough free space before extracting each chunk
+    pub disk_budget: DiskBudget,
+    /// Told how long each stage takes
+    pub progress: &'a Progress,
+    /// Decides each chunk's frame count when a worker starts on it
+    pub planner: &'a Mutex<FramePlanner>,
+}
+
+/// A chunk that needs a video
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Job {
+    pub index: usize,
+    pub time_range: TimeRange,
+    /// An estimate planned ahead of time (e.g. for the disk budget), the worker gets the actual
+    /// count from the `Pipeline::planner` when it starts on the chunk
+    pub frame_count: NonZeroU64,
+    pub treatment: Treatment,
+}
+
+/// How a chunk's video is made
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Treatment {
+    /// With the workers' interpolators
+    Interpolate,
+    /// With `Pipeline::passthrough` frames instead of interpolated ones, for fades/dissolves +
+    /// the parts outside of the --segments with --outside duplicate
+    Duplicate,
+    /// Straight from the input at its own frame rate, for the parts outside of the --segments
+    /// with --outside original
+    Original,
+}
+
+/// A job whose frames were extracted (unless they're streamed), ready to be interpolated
+type Extracted = Result<(Job, Option<PathBuf>), Error>;
+
+/// Frames generated for a chunk, ready to be encoded
+struct Generated {
+    index: usize,
+    dir: PathBuf,
+    glob: String,
+    frames: u64,
+}
+
+/// The channels a worker reads from + writes to
+struct WorkerChannels {
+    extracted: Arc<Mutex<Receiver<Extracted>>>,
+    generated: SyncSender<Generated>,
+    encoded: Sender<(usize, Result<u64, Error>)>,
+    /// set when any worker fails, so the others stop after their current chunk
+    stop: Arc<AtomicBool>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// The most chunks whose frames can be in the temp dir at once with `workers` workers:
+    /// the extractor's chunk waiting for a worker, each worker's chunk (which it holds on to
+    /// until the encoder takes its generated frames), and the chunk being encoded
+    pub fn chunks_in_flight(workers: usize) -> NonZeroUsize {
+        NonZeroUsize::new(workers + 2).unwrap()
+    }
+
+    /// Creates the video for each of the `jobs`
+    /// `on_chunk` is called with each chunk's index + frame count once its video exists (in the
+    /// order they finish), even if another chunk failed
+    /// If the ai binary keeps failing on a chunk (`Error::AICommand`), `on_chunk` gets the error
+    /// and the other chunks carry on. Anything else failing (e.g. ffmpeg, a full disk or being
+    /// interrupted) stops every chunk.
+    pub fn run(
+        &self,
+        jobs: Vec<Job>,
+        mut on_chunk: impl FnMut(usize, Result<u64, Error>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let ffmpeg_stepper = self.ffmpeg_stepper;
+        let raw_format = self.raw_format;
+        let disk_budget = self.disk_budget;
+        let progress = self.progress;
+        let stop = Arc::new(AtomicBool::new(false));
+
+        thread::scope(|scope| {
+            let (extracted_sender, extracted_receiver) = mpsc::sync_channel::<Extracted>(0);
+            let (generated_sender, generated_receiver) = mpsc::sync_channel::<Generated>(0);
+            let (encoded_sender, encoded_receiver) = mpsc::channel();
+
+            let extractor_stop = Arc::clone(&stop);
+            let copied_sender = encoded_sender.clone();
+            scope.spawn(move || {
+                for job in jobs {
+                    if extractor_stop.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    // streamed chunks are extracted by the worker
+                    let extracted = match (job.treatment, raw_format) {
+                        // nothing for the workers to do
+                        (Treatment::Original, _) => {
+                            let started = Instant::now();
+                            match ffmpeg_stepper.copy_chunk(job.index, &job.time_range) {
+                                Ok(frames) => {
+                                    progress.stage_done(Stage::Encode, frames, started.elapsed());
+                                    if copied_sender.send((job.index, Ok(frames))).is_err() {
+                                        break;
+                                    }
+                                    continue;
+                                }
+                                Err(e) => Err(e),
+                            }
+                        }
+                        (_, Some(_)) => Ok((job, None)),
+                        (_, None) => {
+                            let needed = disk_budget
+                                .chunk_bytes(job.time_range.frame_count(), job.frame_count);
+                            disk_budget
+                                .wait_for_space(ffmpeg_stepper.frames_dir(), needed)
+                                .and_then(|_| {
+                                    let started = Instant::now();
+                                    let dir = ffmpeg_stepper
+                                        .extract_frames(job.index, &job.time_range)?;
+                                    let frames = job.time_range.frame_count().get() as u64;
+                                    progress.stage_done(Stage::Extract, frames, started.elapsed());
+                                    Ok(dir)
+                                })
+                                .map(|dir| (job, Some(dir)))
+                        }
+                    };
+                    let failed = extracted.is_err();
+                    // the receiver is gone once every worker stopped
+                    if extracted_sender.send(extracted).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+
+            let extracted_receiver = Arc::new(Mutex::new(extracted_receiver));
+            let workers = self
+                .workers
+                .iter()
+                .map(|interpolation| {
+                    let channels = WorkerChannels {
+                        extracted: Arc::clone(&extracted_receiver),
+                        generated: generated_sender.clone(),
+                        encoded: encoded_sender.clone(),
+                        stop: Arc::clone(&stop),
+                    };
+                    scope.spawn(move || self.work(interpolation, channels))
+                })
+                .collect::<Vec<_>>();
+            // only the workers should keep these channels open
+            drop(extracted_receiver);
+            drop(generated_sender);
+
+            let encoder = scope.spawn(move || -> Result<(), Error> {
+                for generated in generated_receiver {
+                    let started = Instant::now();
+                    ffmpeg_stepper.frames_to_video(
+                        generated.index,
+                        &generated.dir,
+                        &generated.glob,
+                    )?;
+                    progress.stage_done(Stage::Encode, generated.frames, started.elapsed());
+                    fs::remove_dir_all(&generated.dir)?;
+                    if encoded_sender
+                        .send((generated.index, Ok(generated.frames)))
+                        .is_err()
+                    {
+                        break;
+                    }
+                }
+                Ok(())
+            });
+
+            // finished chunks are recorded no matter which worker failed
+            let mut recorded = Ok(());
+            for (index, frames) in encoded_receiver {
+                // the chunks that were already going once recording failed are thrown away
+                if recorded.is_err() {
+                    continue;
+                }
+                if let (Ok(frames), Ok(mut planner)) = (&frames, self.planner.lock()) {
+                    planner.done(index, *frames);
+                }
+                recorded = on_chunk(index, frames);
+                if let Err(e) = &recorded {
+                    warn!("Stopping, unable to record chunk {index}: {e}");
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+
+            let mut results = workers
+                .into_iter()
+                .map(|worker| worker.join())
+                .collect::<Vec<_>>();
+            results.push(encoder.join());
+            let stages = results.into_iter().try_for_each(|result| {
+                result.unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            });
+            recorded.and(stages)
+        })
+    }
+
+    /// Interpolates (or streams) chunks until there are none left, or any worker failed
+    /// Returning drops this worker's channels, so the other stages stop once every worker did
+    fn work(&self, interpolation: &Interpolation, channels: WorkerChannels) -> Result<(), Error> {
+        let result = self.work_until_done(interpolation, &channels);
+        if let Err(e) = &result {
+            warn!("{} failed: {e}", interpolation.interpolator.name());
+            channels.stop.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// Replaces the `extracted` job's estimated frame count with the `planner`'s
+    fn plan(&self, interpolation: &Interpolation, extracted: Extracted) -> Extracted {
+        let (mut job, input_dir) = extracted?;
+        if let Ok(mut planner) = self.planner.lock() {
+            job.frame_count = planner.plan(job.index, &job.time_range, |accountant| {
+                match input_dir {
+                    Some(_) => interpolation.frame_count(job.index, &job.time_range, accountant),
+                    // streamed chunks aren't extracted ahead of time
+                    None => Ok(interpolation.expected_frame_count(&job.time_range, accountant)),
+                }
+            })?;
+        }
+        Ok((job, input_dir))
+    }
+
+    fn work_until_done(
+        &self,
+        interpolation: &Interpolation,
+        channels: &WorkerChannels,
+    ) -> Result<(), Error> {
+        while !channels.stop.load(Ordering::Relaxed) {
+            let extracted = match channels.extracted.lock() {
+                // planned before another worker can take the next chunk, so the chunks are
+                // planned in order
+                Ok(extracted) => extracted
+                    .recv()
+                    .map(|extracted| self.plan(interpolation, extracted)),
+                // another worker panicked
+                Err(_) => break,
+            };
+            // no chunks left
+            let Ok(extracted) = extracted else {
+                break;
+            };
+            let (job, input_dir) = extracted?;
+            let passthrough;
+            let interpolation = match job.treatment {
+                Treatment::Duplicate => {
+                    passthrough = interpolation.using(self.passthrough);
+                    &passthrough
+                }
+                _ => interpolation,
+            };
+
+            let Some(input_dir) = input_dir else {
+                let raw_format = self.raw_format.ok_or(Error::StreamingUnsupported(
+                    interpolation.interpolator.name(),
+                ))?;
+                let started = Instant::now();
+                let streamed = interpolation.with_retry(job.index, |interpolator| {
+                    self.ffmpeg_stepper.stream_chunk(
+                        job.index,
+                        &job.time_range,
+                        interpolator,
+                        raw_format,
+                        job.frame_count,
+                    )
+                });
+                let streamed = match streamed {
+                    Ok(frames) => {
+                        self.progress
+                            .stage_done(Stage::Interpolate, frames, started.elapsed());
+                        Ok(frames)
+                    }
+                    // giving up on this chunk, but not the others
+                    Err(e @ Error::AICommand(_)) => Err(e),
+                    Err(e) => return Err(e),
+                };
+                if channels.encoded.send((job.index, streamed)).is_err() {
+                    break;
+                }
+                continue;
+            };
+
+            let started = Instant::now();
+            let output_dir = match interpolation.execute(job.index, job.frame_count) {
+                Ok(output_dir) => output_dir,
+                // giving up on this chunk, but not the others
+                Err(e @ Error::AICommand(_)) => {
+                    fs::remove_dir_all(input_dir)?;
+                    let output_dir = interpolation.chunk_output_dir(job.index);
+                    if output_dir.exists() {
+                        fs::remove_dir_all(output_dir)?;
+                    }
+                    if channels.encoded.send((job.index, Err(e))).is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let frames = interpolation.output_frame_count(job.index)?;
+            self.progress
+                .stage_done(Stage::Interpolate, frames, started.elapsed());
+            fs::remove_dir_all(input_dir)?;
+            debug!(
+                "{} interpolated chunk {} into {frames} frames",
+                interpolation.interpolator.name(),
+                job.index
+            );
+
+            let generated = Generated {
+                index: job.index,
+                dir: output_dir,
+                glob: interpolation.interpolator.output_glob().to_owned(),
+                frames,
+            };
+            // the receiver is gone if encoding failed
+            if channels.generated.send(generated).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
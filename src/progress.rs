@@ -0,0 +1,284 @@
+use crate::FPS;
+use clap::ValueEnum;
+use serde::Serialize;
+use std::io;
+use std::io::IsTerminal;
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+use strum_macros::Display;
+
+/// How progress is shown
+#[derive(ValueEnum, Copy, Clone, Debug, Default, Display, PartialEq, Eq)]
+#[strum(serialize_all = "lowercase")]
+pub enum ProgressMode {
+    /// A bar if stderr is a terminal, otherwise plain
+    #[default]
+    Auto,
+    /// A progress bar on stderr that updates in place
+    Bar,
+    /// A line on stderr for every finished chunk
+    Plain,
+    /// A json object on stdout for every finished chunk (see `Report`)
+    Json,
+}
+
+/// The steps each chunk goes through
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// extracted frames from the input
+    Extract,
+    /// generated frames (or everything, when streaming)
+    Interpolate,
+    /// encoded the generated frames
+    Encode,
+}
+
+/// What's shown to the user
+/// Also the format of each line with `--progress json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub chunks_done: usize,
+    pub chunks: usize,
+    /// of the input video
+    pub seconds_done: f64,
+    pub total_seconds: f64,
+    /// frames per second going through each stage, `None` until a chunk went through it
+    pub extract_fps: Option<f64>,
+    pub interpolate_fps: Option<f64>,
+    pub encode_fps: Option<f64>,
+    /// `None` until a chunk was finished by this run
+    pub eta_seconds: Option<u64>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct StageStats {
+    frames: u64,
+    busy: Duration,
+}
+
+impl StageStats {
+    fn fps(&self) -> Option<f64> {
+        let busy = self.busy.as_secs_f64();
+        (busy > 0.0).then(|| self.frames as f64 / busy)
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    chunks_done: usize,
+    /// input frames in the finished chunks
+    frames_done: usize,
+    /// input frames finished by this run (not resumed from a previous one)
+    frames_this_run: usize,
+    extract: StageStats,
+    interpolate: StageStats,
+    encode: StageStats,
+}
+
+/// Keeps track of (and shows) how far along the chunks are
+/// Shared by the pipeline's threads
+#[derive(Debug)]
+pub struct Progress {
+    mode: ProgressMode,
+    source_fps: FPS,
+    chunks: usize,
+    /// input frames in the whole video
+    total_frames: usize,
+    started: Instant,
+    state: Mutex<State>,
+}
+
+impl Progress {
+    /// `ProgressMode::Auto` is resolved here
+    pub fn new(mode: ProgressMode, source_fps: FPS, chunks: usize, total_frames: usize) -> Self {
+        let mode = match mode {
+            ProgressMode::Auto if io::stderr().is_terminal() => ProgressMode::Bar,
+            ProgressMode::Auto => ProgressMode::Plain,
+            mode => mode,
+        };
+        Self {
+            mode,
+            source_fps,
+            chunks,
+            total_frames,
+            started: Instant::now(),
+            state: Mutex::new(State {
+                chunks_done: 0,
+                frames_done: 0,
+                frames_this_run: 0,
+                extract: StageStats::default(),
+                interpolate: StageStats::default(),
+                encode: StageStats::default(),
+            }),
+        }
+    }
+
+    /// Chunks a previous run already finished
+    pub fn resume(&self, chunks: usize, frames: usize) {
+        if let Ok(mut state) = self.state.lock() {
+            state.chunks_done += chunks;
+            state.frames_done += frames;
+        }
+    }
+
+    /// `frames` went through `stage` in `busy` time
+    pub fn stage_done(&self, stage: Stage, frames: u64, busy: Duration) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let stats = match stage {
+            Stage::Extract => &mut state.extract,
+            Stage::Interpolate => &mut state.interpolate,
+            Stage::Encode => &mut state.encode,
+        };
+        stats.frames += frames;
+        stats.busy += busy;
+        if self.mode == ProgressMode::Bar {
+            let report = self.report(&state);
+            drop(state);
+            self.show(&report);
+        }
+    }
+
+    /// A chunk with `frames` input frames has its video
+    pub fn chunk_done(&self, frames: usize) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.chunks_done += 1;
+        state.frames_done += frames;
+        state.frames_this_run += frames;
+        let report = self.report(&state);
+        drop(state);
+        self.show(&report);
+    }
+
+    /// Moves the bar out of the way
+    pub fn finish(&self) {
+        if self.mode == ProgressMode::Bar {
+            eprintln!();
+        }
+    }
+
+    fn report(&self, state: &State) -> Report {
+        let seconds = |frames: usize| {
+            frames as f64 * self.source_fps.denominator().get() as f64
+                / self.source_fps.numerator().get() as f64
+        };
+        // based on how fast this run has been going, so resumed chunks don't count
+        let eta_seconds = (state.frames_this_run > 0).then(|| {
+            let remaining = self.total_frames.saturating_sub(state.frames_done);
+            let per_frame = self.started.elapsed().as_secs_f64() / state.frames_this_run as f64;
+            (remaining as f64 * per_frame).round() as u64
+        });
+        Report {
+            chunks_done: state.chunks_done,
+            chunks: self.chunks,
+            seconds_done: seconds(state.frames_done),
+            total_seconds: seconds(self.total_frames),
+            extract_fps: state.extract.fps(),
+            interpolate_fps: state.interpolate.fps(),
+            encode_fps: state.encode.fps(),
+            eta_seconds,
+        }
+    }
+
+    fn show(&self, report: &Report) {
+        // progress isn't worth failing over
+        let _ = match self.mode {
+            ProgressMode::Bar => {
+                let mut stderr = io::stderr().lock();
+                write!(stderr, "\r{}\x1b[K", bar_line(report)).and_then(|_| stderr.flush())
+            }
+            ProgressMode::Json => match serde_json::to_string(report) {
+                Ok(json) => writeln!(io::stdout().lock(), "{json}"),
+                Err(_) => Ok(()),
+            },
+            ProgressMode::Plain | ProgressMode::Auto => {
+                writeln!(io::stderr().lock(), "{}", plain_line(report))
+            }
+        };
+    }
+}
+
+const BAR_WIDTH: usize = 30;
+
+fn bar_line(report: &Report) -> String {
+    let filled = match report.total_seconds > 0.0 {
+        true => (report.seconds_done / report.total_seconds * BAR_WIDTH as f64) as usize,
+        false => 0,
+    }
+    .min(BAR_WIDTH);
+    format!(
+        "[{}{}] {}",
+        "#".repeat(filled),
+        "-".repeat(BAR_WIDTH - filled),
+        plain_line(report)
+    )
+}
+
+fn plain_line(report: &Report) -> String {
+    let fps = |fps: Option<f64>| fps.map_or(String::from("-"), |fps| format!("{fps:.1}"));
+    let eta = report
+        .eta_seconds
+        .map_or(String::from("-"), |eta| duration(Duration::from_secs(eta)));
+    format!(
+        "chunk {}/{} | {:.1}/{:.1}s | fps: extract {} interpolate {} encode {} | ETA {eta}",
+        report.chunks_done,
+        report.chunks,
+        report.seconds_done,
+        report.total_seconds,
+        fps(report.extract_fps),
+        fps(report.interpolate_fps),
+        fps(report.encode_fps),
+    )
+}
+
+/// hh:mm:ss
+fn duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn report() -> Report {
+        Report {
+            chunks_done: 3,
+            chunks: 12,
+            seconds_done: 30.0,
+            total_seconds: 120.0,
+            extract_fps: Some(240.0),
+            interpolate_fps: Some(61.25),
+            encode_fps: None,
+            eta_seconds: Some(3725),
+        }
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(
+            plain_line(&report()),
+            "chunk 3/12 | 30.0/120.0s | fps: extract 240.0 interpolate 61.2 encode - | ETA 01:02:05"
+        );
+        assert!(bar_line(&report()).starts_with("[#######-----------------------] chunk 3/12"));
+    }
+
+    #[test]
+    fn json() {
+        let actual = serde_json::to_string(&report()).unwrap();
+        assert_eq!(
+            actual,
+            r#"{"chunks_done":3,"chunks":12,"seconds_done":30.0,"total_seconds":120.0,"extract_fps":240.0,"interpolate_fps":61.25,"encode_fps":null,"eta_seconds":3725}"#
+        );
+    }
+}
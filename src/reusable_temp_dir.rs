@@ -1,4 +1,7 @@
+use crate::Error;
+use crate::Manifest;
 use clap::ValueEnum;
+use log::warn;
 use std::fs;
 use std::io;
 use std::path::Path;
@@ -10,12 +13,17 @@ pub struct ReusableTempDir {
     base_dir: PathBuf,
     ffmpeg_dir: PathBuf,
     generated_frames_dir: PathBuf,
+    manifest_file: PathBuf,
+    /// failed commands are logged here, see `command::set_log_file`
+    log_file: PathBuf,
 }
 
 impl ReusableTempDir {
     pub fn try_new(base_dir: PathBuf, reset_data: ResetData) -> Result<Self, io::Error> {
         let ffmpeg_dir = base_dir.join("ffmpeg");
         let generated_frames_dir = base_dir.join("generated_frames");
+        let manifest_file = base_dir.join("manifest.json");
+        let log_file = base_dir.join("more-fps.log");
 
         match reset_data {
             ResetData::Everything => fs::remove_dir_all(&base_dir).unwrap_or_default(),
@@ -30,6 +38,8 @@ impl ReusableTempDir {
             base_dir,
             ffmpeg_dir,
             generated_frames_dir,
+            manifest_file,
+            log_file,
         })
     }
 
@@ -41,6 +51,35 @@ impl ReusableTempDir {
         &self.generated_frames_dir
     }
 
+    pub fn log_file(&self) -> &PathBuf {
+        &self.log_file
+    }
+
+    /// The manifest from a previous run (if there was one)
+    /// A manifest we can't parse (e.g. from another version) is ignored, because we can't tell
+    /// which settings created the existing data
+    pub fn load_manifest(&self) -> Result<Option<Manifest>, Error> {
+        if !self.manifest_file.exists() {
+            return Ok(None);
+        }
+        let json = fs::read_to_string(&self.manifest_file)?;
+        match serde_json::from_str(&json) {
+            Ok(manifest) => Ok(Some(manifest)),
+            Err(e) => {
+                warn!("Ignoring {:?}: {e}", self.manifest_file);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Writes to a temporary file first so cancelling mid-write can't corrupt the manifest
+    pub fn save_manifest(&self, manifest: &Manifest) -> Result<(), Error> {
+        let temp_file = self.manifest_file.with_extension("json.tmp");
+        fs::write(&temp_file, serde_json::to_string_pretty(manifest)?)?;
+        fs::rename(&temp_file, &self.manifest_file)?;
+        Ok(())
+    }
+
     pub fn delete(self) -> Result<(), io::Error> {
         fs::remove_dir_all(self.base_dir)?;
         Ok(())
@@ -60,7 +99,8 @@ pub enum ResetData {
     /// Delete the entire temp_directory which contains a few building blocks:
     ///   "ffmpeg" - used for storing extracted frames
     ///   "generated_frames" - used for storing generated frames
-    ///   "scene_data.txt" - holds scene timestamps
+    ///   "scene_scores*.txt" - hold the scene score of every frame (in each window)
+    ///   "manifest.json" - settings and progress of the previous run
     #[default]
     Everything,
     /// Nothing will be deleted... meaning we try to continue from where we left off
@@ -0,0 +1,436 @@
+use crate::time_ranges;
+use crate::Error;
+use crate::TimeRange;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+
+/// How different a frame is from the one before it, according to ffmpeg's scene filter
+/// Every frame's score is cached, so the scenes can be found again with different settings
+/// without reading the whole video again
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SceneScore {
+    /// frame number in the input
+    pub frame: usize,
+    /// 0 (the same) to 1 (completely different)
+    pub score: Decimal,
+}
+
+impl SceneScore {
+    /// A line in the scene cache
+    pub fn to_cache_line(&self) -> String {
+        format!("{} {}", self.frame, self.score)
+    }
+
+    pub fn from_cache_line(line: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidSceneCache(line.to_owned());
+        let (frame, score) = line.split_once(' ').ok_or_else(invalid)?;
+        Ok(Self {
+            frame: frame.parse().map_err(|_| invalid())?,
+            score: Decimal::from_str_exact(score).map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Decides where the scenes start/end from the `SceneScore`s
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SceneDetection {
+    /// A frame whose score is above this starts a new scene (`-s`)
+    /// With `adaptive`, this is the lowest score a cut can have
+    pub threshold: Decimal,
+    /// Cuts closer together than this are merged, keeping the one with the highest score
+    /// Flickering lights/flashes would otherwise cause lots of tiny scenes
+    pub min_frames: usize,
+    /// Longer scenes are split into chunks of at most this many frames
+    pub max_frames: NonZeroUsize,
+    /// After a cut, the score has to drop below `threshold - hysteresis` before another cut
+    /// `None` means every frame above the threshold is a cut
+    pub hysteresis: Option<Decimal>,
+    /// Raises the threshold where the scores are high anyway (e.g. fast action)
+    pub adaptive: Option<AdaptiveThreshold>,
+    /// Finds fades/dissolves, which become their own scenes
+    pub fades: Option<FadeDetection>,
+}
+
+/// A frame is only a cut if its score is far above the scores around it:
+/// `median + sensitivity * MAD` (median absolute deviation) of the `window` frames on each side
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdaptiveThreshold {
+    pub window: usize,
+    pub sensitivity: Decimal,
+}
+
+/// Fades + dissolves change the picture a little every frame, so no single frame is a cut, but
+/// the ai still blends two different pictures (and ghosts)
+/// A run of frames scoring above `threshold` (but not a cut) that adds up to more than a cut is
+/// a fade (the twin comparison method)
+/// Camera movement looks similar, so runs longer than `max_frames` aren't fades
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FadeDetection {
+    pub threshold: Decimal,
+    pub min_frames: usize,
+    pub max_frames: usize,
+}
+
+/// How the video is split up, see `SceneDetection::scenes`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenes {
+    pub time_ranges: Vec<TimeRange>,
+    /// The frames in fades/dissolves, which should be passed through instead of interpolated
+    /// Each time range is either inside one of these, or outside all of them
+    pub fades: Vec<Range<usize>>,
+}
+
+impl Scenes {
+    pub fn is_fade(&self, time_range: &TimeRange) -> bool {
+        self.fades
+            .iter()
+            .any(|fade| fade.start <= time_range.start && time_range.end() <= fade.end)
+    }
+}
+
+impl SceneDetection {
+    /// Splits `0..end` into scenes, then splits the long scenes (see `time_ranges::split`)
+    /// `scores` should be in order
+    /// The `forced` cuts (e.g. chapters) are always kept, even when they're inside a fade
+    pub fn scenes(
+        &self,
+        scores: &[SceneScore],
+        forced: &[usize],
+        end: NonZeroUsize,
+    ) -> Result<Scenes, Error> {
+        self.scenes_in(scores, forced, 0, end)
+    }
+
+    /// Like `scenes`, but only splits `start..end` (e.g. a chunk that's rerendered), as if
+    /// `start` was the start of the video
+    /// The scores around it still count towards the adaptive thresholds
+    pub fn scenes_in(
+        &self,
+        scores: &[SceneScore],
+        forced: &[usize],
+        start: usize,
+        end: NonZeroUsize,
+    ) -> Result<Scenes, Error> {
+        let thresholds = self.thresholds(scores);
+        let inside = scores.partition_point(|scene_score| scene_score.frame < start)
+            ..scores.partition_point(|scene_score| scene_score.frame < end.get());
+        let (scores, thresholds) = (&scores[inside.clone()], &thresholds[inside]);
+        let fades = self.fades(scores, thresholds);
+        let mut cuts = self.scene_changes(scores, thresholds, start, end);
+        // each fade is its own scene
+        cuts.retain(|cut| !fades.iter().any(|fade| fade.contains(cut)));
+        cuts.extend(fades.iter().flat_map(|fade| [fade.start, fade.end]));
+        // a detected cut is usually a frame or two away from the chapter marker of the same cut
+        cuts.retain(|cut| {
+            !forced
+                .iter()
+                .any(|forced| cut.abs_diff(*forced) < self.min_frames.max(1))
+        });
+        cuts.extend(forced);
+        cuts.push(start);
+        cuts.sort_unstable();
+        cuts.dedup();
+        let mut time_ranges = time_ranges::split(&cuts, self.max_frames, end)?;
+        time_ranges.retain(|time_range| time_range.start >= start);
+        Ok(Scenes { time_ranges, fades })
+    }
+
+    /// The score each frame needs to be a cut
+    fn thresholds(&self, scores: &[SceneScore]) -> Vec<Decimal> {
+        let Some(adaptive) = self.adaptive else {
+            return vec![self.threshold; scores.len()];
+        };
+        let mut window = Vec::with_capacity(adaptive.window * 2);
+        (0..scores.len())
+            .map(|index| {
+                window.clear();
+                let neighbours = index.saturating_sub(adaptive.window)
+                    ..(index + adaptive.window + 1).min(scores.len());
+                window.extend(
+                    neighbours
+                        .filter(|&neighbour| neighbour != index)
+                        .map(|neighbour| scores[neighbour].score),
+                );
+                let Some(median) = median(&mut window) else {
+                    return self.threshold;
+                };
+                for score in &mut window {
+                    *score = (*score - median).abs();
+                }
+                let deviation = self::median(&mut window).unwrap_or_default();
+                (median + adaptive.sensitivity * deviation).max(self.threshold)
+            })
+            .collect()
+    }
+
+    /// The frames above their threshold, after applying the `hysteresis` + `min_frames`
+    fn scene_changes(
+        &self,
+        scores: &[SceneScore],
+        thresholds: &[Decimal],
+        start: usize,
+        end: NonZeroUsize,
+    ) -> Vec<usize> {
+        // the `start` is always a cut, but it can't be moved
+        let mut cuts: Vec<SceneScore> = vec![];
+        let mut armed = true;
+        for (&scene_score, &threshold) in scores.iter().zip(thresholds) {
+            if let Some(hysteresis) = self.hysteresis {
+                if scene_score.score < threshold - hysteresis {
+                    armed = true;
+                }
+            }
+            if scene_score.score <= threshold || !armed {
+                continue;
+            }
+            armed = self.hysteresis.is_none();
+
+            let previous = cuts.last().map_or(start, |cut| cut.frame);
+            if scene_score.frame <= previous {
+                continue;
+            }
+            if scene_score.frame - previous >= self.min_frames {
+                cuts.push(scene_score);
+            } else if let Some(last) = cuts
+                .last_mut()
+                .filter(|last| scene_score.score > last.score)
+            {
+                *last = scene_score;
+            }
+        }
+        // the last scene is too short too
+        while cuts
+            .last()
+            .is_some_and(|cut| end.get().saturating_sub(cut.frame) < self.min_frames)
+        {
+            cuts.pop();
+        }
+        cuts.into_iter().map(|cut| cut.frame).collect()
+    }
+
+    /// The frames in each fade, see `FadeDetection`
+    fn fades(&self, scores: &[SceneScore], thresholds: &[Decimal]) -> Vec<Range<usize>> {
+        let Some(fade_detection) = self.fades else {
+            return vec![];
+        };
+        let is_fade = |(frames, total): &(Range<usize>, Decimal)| {
+            (fade_detection.min_frames..=fade_detection.max_frames).contains(&frames.len())
+                && *total > self.threshold
+        };
+        let mut fades = vec![];
+        // the frames in the current run + their total score
+        let mut run: Option<(Range<usize>, Decimal)> = None;
+        for (scene_score, &threshold) in scores.iter().zip(thresholds) {
+            let in_run =
+                scene_score.score > fade_detection.threshold && scene_score.score <= threshold;
+            if let Some((frames, total)) = run.as_mut().filter(|(frames, _)| {
+                // without any missing scores in between
+                in_run && frames.end == scene_score.frame
+            }) {
+                frames.end += 1;
+                *total += scene_score.score;
+                continue;
+            }
+            fades.extend(run.take().filter(is_fade).map(|(frames, _)| frames));
+            if in_run {
+                run = Some((scene_score.frame..scene_score.frame + 1, scene_score.score));
+            }
+        }
+        fades.extend(run.filter(is_fade).map(|(frames, _)| frames));
+        fades
+    }
+}
+
+/// Moves the middle value to the middle of `values`
+fn median(values: &mut [Decimal]) -> Option<Decimal> {
+    if values.is_empty() {
+        return None;
+    }
+    let middle = values.len() / 2;
+    let (_, median, _) = values.select_nth_unstable(middle);
+    Some(*median)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scores(scores: &[(usize, &str)]) -> Vec<SceneScore> {
+        scores
+            .iter()
+            .map(|&(frame, score)| SceneScore {
+                frame,
+                score: Decimal::from_str_exact(score).unwrap(),
+            })
+            .collect()
+    }
+
+    fn detection() -> SceneDetection {
+        SceneDetection {
+            threshold: Decimal::from_str_exact("0.3").unwrap(),
+            min_frames: 0,
+            max_frames: NonZeroUsize::new(1000).unwrap(),
+            hysteresis: None,
+            adaptive: None,
+            fades: None,
+        }
+    }
+
+    /// Where each scene starts, other than the start of the video
+    fn cuts(detection: &SceneDetection, scores: &[SceneScore], end: usize) -> Vec<usize> {
+        let scenes = detection
+            .scenes(scores, &[], NonZeroUsize::new(end).unwrap())
+            .unwrap();
+        scenes.time_ranges[1..]
+            .iter()
+            .map(|time_range| time_range.start)
+            .collect()
+    }
+
+    #[test]
+    fn cache_line() {
+        let score = scores(&[(120, "0.504959")])[0];
+        assert_eq!(score.to_cache_line(), "120 0.504959");
+        assert_eq!(SceneScore::from_cache_line("120 0.504959").unwrap(), score);
+        assert!(SceneScore::from_cache_line("9.76").is_err());
+    }
+
+    #[test]
+    fn threshold() {
+        let scores = scores(&[
+            (0, "0"),
+            (10, "0.5"),
+            (11, "0.4"),
+            (50, "0.1"),
+            (100, "0.9"),
+            (150, "0.3"),
+        ]);
+        assert_eq!(cuts(&detection(), &scores, 200), [10, 11, 100]);
+
+        let mut detection = detection();
+        detection.threshold = Decimal::from_str_exact("0.45").unwrap();
+        assert_eq!(cuts(&detection, &scores, 200), [10, 100]);
+    }
+
+    #[test]
+    fn min_length() {
+        // a flash: a cut into and out of it, close together
+        let scores = scores(&[
+            (3, "0.8"),
+            (60, "0.5"),
+            (62, "0.7"),
+            (64, "0.6"),
+            (120, "0.9"),
+            (195, "0.9"),
+        ]);
+        let mut detection = detection();
+        detection.min_frames = 10;
+        // too close to the start/end, and only the strongest cut of the flash is kept
+        assert_eq!(cuts(&detection, &scores, 200), [62, 120]);
+    }
+
+    #[test]
+    fn hysteresis() {
+        let scores = scores(&[
+            (10, "0.5"),
+            (11, "0.35"),
+            (12, "0.25"),
+            (13, "0.5"),
+            (14, "0.1"),
+            (15, "0.5"),
+        ]);
+        let mut detection = detection();
+        detection.hysteresis = Some(Decimal::from_str_exact("0.1").unwrap());
+        // 0.25 isn't below 0.3 - 0.1, so 13 isn't a cut
+        assert_eq!(cuts(&detection, &scores, 200), [10, 15]);
+    }
+
+    #[test]
+    fn max_length() {
+        let mut detection = detection();
+        detection.max_frames = NonZeroUsize::new(100).unwrap();
+        assert_eq!(cuts(&detection, &scores(&[(50, "1")]), 250), [50, 150]);
+    }
+
+    #[test]
+    fn adaptive() {
+        // fast action with a cut at 30, then a slow scene with a (weaker) cut at 80
+        let action = ["0.3", "0.32", "0.34", "0.36", "0.38", "0.4", "0.42"];
+        let mut scores = (0..60)
+            .map(|frame| (frame, action[frame % action.len()]))
+            .chain((60..120).map(|frame| (frame, "0.01")))
+            .collect::<Vec<_>>();
+        scores[30].1 = "0.95";
+        scores[80].1 = "0.2";
+        let scores = self::scores(&scores);
+
+        let mut detection = detection();
+        detection.threshold = Decimal::from_str_exact("0.1").unwrap();
+        // every frame of the action is above the threshold
+        assert_eq!(cuts(&detection, &scores, 120).len(), 60);
+        detection.adaptive = Some(AdaptiveThreshold {
+            window: 10,
+            sensitivity: Decimal::from(5),
+        });
+        assert_eq!(cuts(&detection, &scores, 120), [30, 80]);
+    }
+
+    #[test]
+    fn fades() {
+        // a dissolve from 40 to 52, then a pan from 100 to 180
+        let scores = (0..200)
+            .map(|frame| match frame {
+                40..=51 => (frame, "0.05"),
+                100..=179 => (frame, "0.04"),
+                _ => (frame, "0.001"),
+            })
+            .collect::<Vec<_>>();
+        let scores = self::scores(&scores);
+        let mut detection = detection();
+        detection.fades = Some(FadeDetection {
+            threshold: Decimal::from_str_exact("0.02").unwrap(),
+            min_frames: 6,
+            max_frames: 48,
+        });
+        let end = NonZeroUsize::new(200).unwrap();
+        let scenes = detection.scenes(&scores, &[], end).unwrap();
+        assert_eq!(scenes.fades, vec![Range { start: 40, end: 52 }]);
+        assert_eq!(cuts(&detection, &scores, 200), [40, 52]);
+        assert!(scenes.is_fade(&scenes.time_ranges[1]));
+        assert!(!scenes.is_fade(&scenes.time_ranges[2]));
+    }
+
+    #[test]
+    fn forced() {
+        let scores = scores(&[(10, "0.5"), (99, "0.9"), (150, "0.01")]);
+        let mut detection = detection();
+        detection.min_frames = 5;
+        let end = NonZeroUsize::new(200).unwrap();
+        let scenes = detection.scenes(&scores, &[5, 100, 150], end).unwrap();
+        // 99 is the same cut as 100, forced cuts can be shorter than min_frames
+        let cuts = scenes.time_ranges[1..]
+            .iter()
+            .map(|time_range| time_range.start)
+            .collect::<Vec<_>>();
+        assert_eq!(cuts, [5, 10, 100, 150]);
+    }
+
+    #[test]
+    fn within() {
+        let scores = scores(&[(10, "0.5"), (105, "0.9"), (130, "0.6"), (190, "0.7")]);
+        let mut detection = detection();
+        detection.min_frames = 10;
+        let end = NonZeroUsize::new(180).unwrap();
+        let scenes = detection.scenes_in(&scores, &[], 100, end).unwrap();
+        // 105 is too close to the start, and 190 is after the end
+        let time_ranges = scenes
+            .time_ranges
+            .iter()
+            .map(|time_range| (time_range.start, time_range.end()))
+            .collect::<Vec<_>>();
+        assert_eq!(time_ranges, [(100, 130), (130, 180)]);
+    }
+}
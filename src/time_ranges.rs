@@ -1,40 +1,48 @@
-use crate::NonZeroDecimal;
-use rust_decimal::Decimal;
+use crate::Error;
+use serde::Deserialize;
+use serde::Serialize;
 use std::num::NonZeroUsize;
 
-#[derive(Debug, PartialEq)]
+/// A chunk of the source video, in frame numbers
+/// `start` is inclusive and `end` is exclusive, so consecutive ranges tile the video without
+/// gaps or overlaps
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
 pub struct TimeRange {
-    pub start: Decimal,
-    end: NonZeroDecimal,
+    pub start: usize,
+    end: NonZeroUsize,
 }
 
 impl TimeRange {
-    pub fn duration(&self) -> NonZeroDecimal {
-        // unwrapping because this struct can only be created via
-        // TimeRange's next which has an if statement to check if start == *end
+    pub fn end(&self) -> usize {
+        self.end.get()
+    }
 
-        // rounding to 3 decimal places to confirm we always extract at least 1 frame
-        NonZeroDecimal::try_new((*self.end - self.start).round_dp(3)).unwrap()
+    /// Number of source frames in this range
+    pub fn frame_count(&self) -> NonZeroUsize {
+        // unwrapping because this struct can only be created via
+        // TimeRange's next which has an if statement to check if start == end
+        NonZeroUsize::new(self.end.get() - self.start).unwrap()
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct TimeRanges {
-    start: Decimal,
+    start: usize,
     max_step_size: NonZeroUsize,
-    end: NonZeroDecimal,
+    end: NonZeroUsize,
 }
 
 impl TimeRanges {
+    /// All of the arguments are frame numbers/counts
     pub fn try_new<A, B, C>(start: A, max_step_size: B, end: C) -> Option<Self>
     where
-        A: TryInto<Decimal>,
+        A: TryInto<usize>,
         B: TryInto<NonZeroUsize>,
-        C: TryInto<Decimal>,
+        C: TryInto<usize>,
     {
         let start = start.try_into().ok()?;
-        let end = NonZeroDecimal::try_new(end.try_into().ok()?)?;
-        if start >= *end {
+        let end = NonZeroUsize::new(end.try_into().ok()?)?;
+        if start >= end.get() {
             return None;
         }
         let max_step_size = max_step_size.try_into().ok()?;
@@ -44,8 +52,8 @@ impl TimeRanges {
             end,
         })
     }
-    pub fn end(&self) -> &Decimal {
-        &self.end
+    pub fn end(&self) -> usize {
+        self.end.get()
     }
 }
 
@@ -55,58 +63,114 @@ impl Iterator for TimeRanges {
     fn next(&mut self) -> Option<Self::Item> {
         let start = self.start;
         let end = self.end.get();
-        if start == *end {
+        if start == end {
             return None;
         }
-        let step_size: Decimal = self.max_step_size.get().try_into().ok()?;
-        let next_start = vec![(start + step_size).round(), *end]
-            .into_iter()
-            .min()
-            .unwrap();
+        let next_start = start.saturating_add(self.max_step_size.get()).min(end);
         self.start = next_start;
         Some(TimeRange {
             start,
-            end: NonZeroDecimal::try_new(next_start)?,
+            end: NonZeroUsize::new(next_start)?,
+        })
+    }
+}
+
+/// Splits `0..end` at each of the `cuts` (frame numbers), then splits those scenes into steps of
+/// at most `max_step_size` frames
+/// Cuts that are out of order, duplicated or outside of the video are ignored
+pub fn split(
+    cuts: &[usize],
+    max_step_size: NonZeroUsize,
+    end: NonZeroUsize,
+) -> Result<Vec<TimeRange>, Error> {
+    let mut boundaries = vec![0];
+    for &cut in cuts {
+        if cut > *boundaries.last().unwrap() && cut < end.get() {
+            boundaries.push(cut);
+        }
+    }
+    boundaries.push(end.get());
+
+    let time_ranges = boundaries
+        .windows(2)
+        .map(|pair| {
+            TimeRanges::try_new(pair[0], max_step_size, pair[1]).ok_or(
+                Error::UnableToCreateTimeRanges(pair[0], max_step_size, pair[1]),
+            )
         })
+        .collect::<Result<Vec<TimeRanges>, _>>()?;
+
+    Ok(time_ranges.into_iter().flatten().collect())
+}
+
+/// Splits the `time_ranges` at each of the `cuts` that's inside one of them
+/// Used to make chunks end where the windows (`--segments`) do
+pub fn split_at(time_ranges: &[TimeRange], cuts: &[usize]) -> Vec<TimeRange> {
+    let mut split = vec![];
+    for time_range in time_ranges {
+        let mut start = time_range.start;
+        let mut inside = cuts
+            .iter()
+            .filter(|&&cut| time_range.start < cut && cut < time_range.end())
+            .copied()
+            .collect::<Vec<_>>();
+        inside.sort_unstable();
+        inside.dedup();
+        for end in inside.into_iter().chain([time_range.end()]) {
+            split.push(TimeRange {
+                start,
+                // after start, which is at least 0
+                end: NonZeroUsize::new(end).unwrap(),
+            });
+            start = end;
+        }
     }
+    split
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn time_range(start: usize, end: usize) -> TimeRange {
+        TimeRange {
+            start,
+            end: NonZeroUsize::new(end).unwrap(),
+        }
+    }
+
+    /// Every frame in `0..end` belongs to exactly one range
+    fn assert_tiles(time_ranges: &[TimeRange], end: usize) {
+        assert_eq!(time_ranges.first().unwrap().start, 0);
+        assert_eq!(time_ranges.last().unwrap().end(), end);
+        for pair in time_ranges.windows(2) {
+            assert_eq!(pair[0].end(), pair[1].start);
+        }
+        let frames: usize = time_ranges.iter().map(|t| t.frame_count().get()).sum();
+        assert_eq!(frames, end);
+    }
+
     #[test]
     fn flatten() {
-        // 10 seconds worth of frames
-        let max_step_size = NonZeroUsize::new(10).unwrap();
+        // 240 frames at a time
+        let max_step_size = NonZeroUsize::new(240).unwrap();
 
         let time_ranges = vec![
-            TimeRanges::try_new(0, max_step_size, "6.675000").unwrap(),
-            TimeRanges::try_new("6.675000", max_step_size, "35").unwrap(),
+            TimeRanges::try_new(0, max_step_size, 160).unwrap(),
+            TimeRanges::try_new(160, max_step_size, 839).unwrap(),
         ];
         let actual = time_ranges
             .into_iter()
             .flatten()
             .collect::<Vec<TimeRange>>();
         let expected = vec![
-            TimeRange {
-                start: Decimal::ZERO,
-                end: "6.675000".try_into().unwrap(),
-            },
-            TimeRange {
-                start: "6.675000".try_into().unwrap(),
-                end: "17".try_into().unwrap(),
-            },
-            TimeRange {
-                start: "17".try_into().unwrap(),
-                end: "27".try_into().unwrap(),
-            },
-            TimeRange {
-                start: "27".try_into().unwrap(),
-                end: "35".try_into().unwrap(),
-            },
+            time_range(0, 160),
+            time_range(160, 400),
+            time_range(400, 640),
+            time_range(640, 839),
         ];
         assert_eq!(actual, expected);
+        assert_tiles(&actual, 839);
     }
     #[test]
     fn start_equals_end() {
@@ -121,94 +185,81 @@ mod tests {
     }
     #[test]
     fn next_doesnt_exceed_end() {
-        let start = 0;
-
-        // 10 seconds worth of frames
-        let max_step_size = 10;
-
-        let end = Decimal::from_str_exact("9.76").unwrap();
-
-        let mut time_ranges = TimeRanges::try_new(start, max_step_size, end)
-            .unwrap()
-            .into_iter();
-        assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start: Decimal::ZERO,
-                end: NonZeroDecimal::try_new(end).unwrap()
-            }
-        );
+        let mut time_ranges = TimeRanges::try_new(0, 240, 234).unwrap();
+        assert_eq!(time_ranges.next().unwrap(), time_range(0, 234));
         assert!(time_ranges.next().is_none());
     }
 
     #[test]
     fn start_is_zero_multiple_next() {
-        let start = 0;
-        let max_step_size = 3;
-        let end = Decimal::from_str_exact("9.76").unwrap();
-        let mut time_ranges = TimeRanges::try_new(start, max_step_size, end)
+        let max_step_size = 72;
+        let end = 234;
+        let time_ranges = TimeRanges::try_new(0, max_step_size, end)
             .unwrap()
-            .into_iter();
-        assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start: Decimal::ZERO,
-                end: NonZeroDecimal::try_new(max_step_size).unwrap()
-            }
-        );
+            .collect::<Vec<_>>();
         assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start: max_step_size.try_into().unwrap(),
-                end: NonZeroDecimal::try_new(max_step_size * 2).unwrap()
-            }
-        );
-        assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start: (max_step_size * 2).try_into().unwrap(),
-                end: NonZeroDecimal::try_new(max_step_size * 3).unwrap()
-            }
-        );
-        assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start: (max_step_size * 3).try_into().unwrap(),
-                end: NonZeroDecimal::try_new(end).unwrap()
-            }
+            time_ranges,
+            vec![
+                time_range(0, 72),
+                time_range(72, 144),
+                time_range(144, 216),
+                time_range(216, end),
+            ]
         );
+        assert_tiles(&time_ranges, end);
+    }
+
+    #[test]
+    fn steps_are_relative_to_start() {
+        let mut time_ranges = TimeRanges::try_new(12539, 480, 13622).unwrap();
+        assert_eq!(time_ranges.next().unwrap(), time_range(12539, 13019));
+        assert_eq!(time_ranges.next().unwrap(), time_range(13019, 13499));
+        assert_eq!(time_ranges.next().unwrap(), time_range(13499, 13622));
         assert!(time_ranges.next().is_none());
     }
 
     #[test]
-    fn start_is_decimal_multiple_next() {
-        let start = Decimal::from_str_exact("522.981000").unwrap();
-        let end = Decimal::from_str_exact("568.151000").unwrap();
-        let max_step_size = 20;
+    fn split_tiles_the_video() {
+        let max_step_size = NonZeroUsize::new(100).unwrap();
+        let end = NonZeroUsize::new(1000).unwrap();
+        let cuts = [234, 324, 454, 530];
+        let time_ranges = split(&cuts, max_step_size, end).unwrap();
+        assert_tiles(&time_ranges, end.get());
+        assert!(time_ranges.iter().all(|t| t.frame_count() <= max_step_size));
+        // every cut starts a new range
+        for cut in cuts {
+            assert!(time_ranges.iter().any(|t| t.start == cut));
+        }
+    }
 
-        let mut time_ranges = TimeRanges::try_new(start, max_step_size, end)
-            .unwrap()
-            .into_iter();
-        assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start,
-                end: NonZeroDecimal::try_new(543).unwrap()
-            }
-        );
-        assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start: Decimal::from_str_exact("543").unwrap(),
-                end: NonZeroDecimal::try_new(563).unwrap()
-            }
-        );
+    #[test]
+    fn split_at_cuts() {
+        let time_ranges = split(
+            &[100],
+            NonZeroUsize::new(1000).unwrap(),
+            NonZeroUsize::new(300).unwrap(),
+        )
+        .unwrap();
+        let split = split_at(&time_ranges, &[50, 100, 150, 120, 300]);
         assert_eq!(
-            time_ranges.next().unwrap(),
-            TimeRange {
-                start: Decimal::from_str_exact("563").unwrap(),
-                end: NonZeroDecimal::try_new(end).unwrap()
-            }
+            split,
+            [
+                time_range(0, 50),
+                time_range(50, 100),
+                time_range(100, 120),
+                time_range(120, 150),
+                time_range(150, 300)
+            ]
         );
     }
+
+    #[test]
+    fn split_ignores_bad_cuts() {
+        let max_step_size = NonZeroUsize::new(1000).unwrap();
+        let end = NonZeroUsize::new(500).unwrap();
+        // 0, duplicates, out of order, and past the end
+        let cuts = [0, 100, 100, 50, 500, 600];
+        let time_ranges = split(&cuts, max_step_size, end).unwrap();
+        assert_eq!(time_ranges, vec![time_range(0, 100), time_range(100, 500)]);
+    }
 }
@@ -0,0 +1,166 @@
+use crate::cuts;
+use crate::Error;
+use crate::FPS;
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::ops::Range;
+use std::str::FromStr;
+use strum_macros::Display;
+
+/// A part of the input to process (`--start`/`--end` or one of the `--segments`), in seconds
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Window {
+    pub start: Decimal,
+    /// `None` is the end of the input
+    #[serde(deserialize_with = "crate::manifest::required")]
+    pub end: Option<Decimal>,
+}
+
+/// `start-end`, e.g. `1:00-1:30.5`
+impl FromStr for Window {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected start-end, like 1:00-1:30.5: {s}");
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = cuts::parse_timestamp(start.trim()).ok_or_else(invalid)?;
+        let end = cuts::parse_timestamp(end.trim()).ok_or_else(invalid)?;
+        if end <= start {
+            return Err(format!("should end after it starts: {s}"));
+        }
+        Ok(Self {
+            start,
+            end: Some(end),
+        })
+    }
+}
+
+/// What happens to the parts of the input outside of the windows
+#[derive(
+    ValueEnum, Copy, Clone, Debug, Default, Display, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[strum(serialize_all = "kebab-case")]
+#[serde(rename_all = "kebab-case")]
+pub enum Outside {
+    /// Left out, so the output is only those parts
+    #[default]
+    Clip,
+    /// Kept at the input's frame rate (a variable frame rate output, so it has to be mkv)
+    Original,
+    /// Kept with their frames duplicated to the new frame rate
+    Duplicate,
+}
+
+/// The `windows` sorted with the overlaps merged, so no part of the input is in two of them
+pub fn merged(windows: &[Window]) -> Vec<Window> {
+    let mut windows = windows.to_vec();
+    windows.sort_unstable_by_key(|window| window.start);
+    let mut merged: Vec<Window> = vec![];
+    for window in windows {
+        match merged.last_mut() {
+            Some(last) if last.end.is_none_or(|end| window.start <= end) => {
+                last.end = last
+                    .end
+                    .zip(window.end)
+                    .map(|(end, window_end)| end.max(window_end));
+            }
+            _ => merged.push(window),
+        }
+    }
+    merged
+}
+
+/// The frames in the `windows` (up to the input's `end`), sorted with the overlaps merged
+/// No `windows` is the whole input
+pub fn frame_ranges(
+    windows: &[Window],
+    source_fps: &FPS,
+    end: usize,
+) -> Result<Vec<Range<usize>>, Error> {
+    if windows.is_empty() {
+        return Ok(vec![Range { start: 0, end }]);
+    }
+    let mut frame_ranges = windows
+        .iter()
+        .map(|window| {
+            let start = source_fps.frame_at(&window.start)?;
+            let window_end = match window.end {
+                Some(window_end) => source_fps.frame_at(&window_end)?.min(end),
+                None => end,
+            };
+            Ok(start..window_end)
+        })
+        .filter(|frames| !frames.as_ref().is_ok_and(Range::is_empty))
+        .collect::<Result<Vec<_>, Error>>()?;
+    frame_ranges.sort_unstable_by_key(|frames| frames.start);
+
+    let mut merged: Vec<Range<usize>> = vec![];
+    for frames in frame_ranges {
+        match merged.last_mut() {
+            Some(last) if frames.start <= last.end => last.end = last.end.max(frames.end),
+            _ => merged.push(frames),
+        }
+    }
+    if merged.is_empty() {
+        return Err(Error::NothingToProcess);
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: Option<&str>) -> Window {
+        Window {
+            start: Decimal::from_str_exact(start).unwrap(),
+            end: end.map(|end| Decimal::from_str_exact(end).unwrap()),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("1:00-1:30.5".parse(), Ok(window("60", Some("90.5"))));
+        assert_eq!("10 - 20".parse(), Ok(window("10", Some("20"))));
+        assert!("20-10".parse::<Window>().is_err());
+        assert!("10".parse::<Window>().is_err());
+    }
+
+    #[test]
+    fn frames() {
+        let fps = FPS::try_new(24, 1).unwrap();
+        let windows = [
+            window("100", None),
+            window("10", Some("20")),
+            window("15", Some("30")),
+            window("1000", Some("1010")),
+        ];
+        assert_eq!(
+            frame_ranges(&windows, &fps, 2880).unwrap(),
+            [240..720, 2400..2880]
+        );
+        assert_eq!(
+            frame_ranges(&[], &fps, 2880).unwrap(),
+            [Range {
+                start: 0,
+                end: 2880
+            }]
+        );
+        assert!(frame_ranges(&windows[3..], &fps, 2880).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let windows = [
+            window("100", None),
+            window("10", Some("20")),
+            window("15", Some("30")),
+            window("120", Some("130")),
+        ];
+        assert_eq!(
+            merged(&windows),
+            [window("10", Some("30")), window("100", None)]
+        );
+    }
+}